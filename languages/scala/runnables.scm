@@ -33,7 +33,10 @@
 ;Common Runnables for Cats Effect
 (
     (
+        (package_clause
+          name: (package_identifier) @scala_package_name)*
         (object_definition
+            name: (identifier) @scala_main_function_name
             extend: (extends_clause
                 type: (type_identifier) @run
             )
@@ -46,7 +49,10 @@
 ;Common Runnables for ZIO
 (
     (
+        (package_clause
+          name: (package_identifier) @scala_package_name)*
         (object_definition
+            name: (identifier) @scala_main_function_name
             extend: (extends_clause
                 type: (type_identifier) @run
             )
@@ -76,7 +82,10 @@
 ; ScalaTest Common Runnables - https://www.scalatest.org/
 (
     (
+        (package_clause
+          name: (package_identifier) @scala_package_name)*
         (class_definition
+            name: (identifier) @scala_test_class_name
             extend: (extends_clause
                 type: (type_identifier) @run
             )
@@ -86,10 +95,34 @@
     (#set! tag scala-test)
 )
 
+; Single test cases of MUnit and ScalaTest FunSuite, defined as `test("name") { ... }`
+(
+    (
+        (package_clause
+          name: (package_identifier) @scala_package_name)*
+        (class_definition
+            name: (identifier) @scala_test_class_name
+            body: (template_body
+                (call_expression
+                    function: (call_expression
+                        function: (identifier) @run
+                        arguments: (arguments . (string) @scala_test_name)
+                    )
+                ) @_scala_test_case_end
+            )
+        )
+        (#eq? @run "test")
+    )
+    (#set! tag scala-test)
+)
+
 ; Munit Common Runnables - outside of FunSuite all other keywords were derived from links found in https://scalameta.org/munit/docs/integrations/external-integrations.html
 (
     (
+        (package_clause
+          name: (package_identifier) @scala_package_name)*
         (class_definition
+            name: (identifier) @scala_test_class_name
             extend: (extends_clause
                 type: (type_identifier) @run
             )
@@ -102,7 +135,10 @@
 ; Specs2 Common Runnables - https://etorreborre.github.io/specs2/guide/SPECS2-5.5.8/org.specs2.guide.UserGuide.html
 (
     (
+        (package_clause
+          name: (package_identifier) @scala_package_name)*
         (class_definition
+            name: (identifier) @scala_test_class_name
             extend: (extends_clause
                 type: (type_identifier) @run
             )
@@ -115,7 +151,10 @@
 ; Weaver Test - https://disneystreaming.github.io/weaver-test/
 (
     (
+        (package_clause
+          name: (package_identifier) @scala_package_name)*
         (object_definition
+            name: (identifier) @scala_test_class_name
             extend: (extends_clause
                 type: (type_identifier) @run
             )
@@ -127,7 +166,10 @@
 
 (
     (
+        (package_clause
+          name: (package_identifier) @scala_package_name)*
         (object_definition
+            name: (identifier) @scala_test_class_name
             extend: (extends_clause
                 type: (type_identifier) @run
             )
@@ -140,7 +182,10 @@
 ; ZIO Test - https://zio.dev/reference/test/
 (
     (
+        (package_clause
+          name: (package_identifier) @scala_package_name)*
         (object_definition
+            name: (identifier) @scala_test_class_name
             extend: (extends_clause
                 type: (type_identifier) @run
             )
@@ -153,7 +198,10 @@
 ; Hedgehog - https://hedgehogqa.github.io/scala-hedgehog/
 (
     (
+        (package_clause
+          name: (package_identifier) @scala_package_name)*
         (class_definition
+            name: (identifier) @scala_test_class_name
             extend: (extends_clause
                 type: (type_identifier) @run
             )
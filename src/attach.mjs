@@ -0,0 +1,188 @@
+// Helper preparing attach mode of the debugger, once Metals has started the debug adapter.
+// It's run by the extension as
+// `node --input-type=module -e <code> <host> <port> <timeout> <interval> [<adapter host> <adapter port> <cwd> <command>]`.
+//
+// The debug port (host and port) is probed, with exponential backoff, until it accepts connections
+// or the timeout (ms) passes. The helper exits with 0 once the port is reachable, or with 1 and the reason on stderr.
+//
+// The optional command (e.g. `kubectl port-forward ...` or `ssh -N -L ...`) is started in the given directory
+// (the worktree) before probing the port, as it usually forwards the port for the whole debug session.
+// To stop it when the session ends,
+// the connection to the debug adapter goes through a relay running in the background:
+// the helper prints the relay's port, and the relay stops the command (with its children)
+// once either the editor or the debug adapter closes the connection.
+
+import { spawn } from "node:child_process";
+import { connect, createServer } from "node:net";
+import { setTimeout as sleep } from "node:timers/promises";
+
+const MAX_INTERVAL = 2_000;
+const PROBE_TIMEOUT = 1_000;
+const RELAY_MODE = "relay";
+const RELAY_HOST = "127.0.0.1";
+const RELAY_ACCEPT_TIMEOUT = 60_000; // Time for the editor to connect to the relay
+
+if (process.argv[1] === RELAY_MODE) {
+  relay(...process.argv.slice(2));
+} else {
+  await prepare(...process.argv.slice(1));
+}
+
+/**
+ * Run the command and wait for the debug port.
+ *
+ * @param {string} host
+ * @param {string} portArg
+ * @param {string} timeoutArg
+ * @param {string} intervalArg
+ * @param {string} [adapterHost]
+ * @param {string} [adapterPort]
+ * @param {string} [cwd] of the command
+ * @param {string} [command]
+ */
+async function prepare(
+  host,
+  portArg,
+  timeoutArg,
+  intervalArg,
+  adapterHost,
+  adapterPort,
+  cwd,
+  command,
+) {
+  const port = Number(portArg);
+  const deadline = Date.now() + Number(timeoutArg);
+  let interval = Number(intervalArg);
+
+  let exited = null; // exit code of the command, if it has stopped
+  let child = null;
+  if (command) {
+    child = spawn(command, { cwd, shell: true, detached: true, stdio: "ignore" });
+    child.on("error", (err) => fail(`Could not start \`${command}\`: ${err.message}`));
+    child.on("exit", (code) => (exited = code ?? "signal"));
+  }
+
+  while (true) {
+    if (await isReachable(host, port)) {
+      break;
+    }
+    if (exited !== null && exited !== 0) {
+      fail(`\`${command}\` exited with ${exited} before ${host}:${port} was reachable`);
+    }
+    if (Date.now() + interval > deadline) {
+      if (child && exited === null) {
+        killGroup(child.pid);
+      }
+      fail(`${host}:${port} was not reachable within ${timeoutArg}ms`);
+    }
+    await sleep(interval);
+    interval = Math.min(interval * 2, MAX_INTERVAL);
+  }
+
+  if (!child) {
+    process.exit(0);
+  }
+  const relayPort = await startRelay(adapterHost, adapterPort, child.pid).catch((err) => {
+    killGroup(child.pid);
+    fail(`Could not start the relay to the debugger: ${err.message}`);
+  });
+  child.unref();
+  process.stdout.write(String(relayPort));
+  process.exit(0);
+}
+
+/**
+ * Start the relay in the background, with the same code, and wait for its port.
+ *
+ * @param {string} adapterHost
+ * @param {string} adapterPort
+ * @param {number} pid of the command
+ * @returns {Promise<number>}
+ */
+function startRelay(adapterHost, adapterPort, pid) {
+  return new Promise((resolve, reject) => {
+    const relay = spawn(
+      process.execPath,
+      [...process.execArgv, RELAY_MODE, adapterHost, adapterPort, String(pid)],
+      { detached: true, stdio: ["ignore", "ignore", "ignore", "ipc"] },
+    );
+    relay.once("error", reject);
+    relay.once("exit", (code) => reject(new Error(`the relay exited with ${code}`)));
+    relay.once("message", ({ port }) => {
+      relay.removeAllListeners("exit");
+      relay.disconnect();
+      relay.unref();
+      resolve(port);
+    });
+  });
+}
+
+/**
+ * Pass the connection of the editor to the debug adapter, and stop the command when it's closed.
+ *
+ * @param {string} adapterHost
+ * @param {string} adapterPort
+ * @param {string} pid of the command
+ */
+function relay(adapterHost, adapterPort, pid) {
+  const stop = () => {
+    killGroup(Number(pid));
+    process.exit(0);
+  };
+  // The editor may never connect, e.g. if it fails to start the session
+  const acceptTimeout = setTimeout(stop, RELAY_ACCEPT_TIMEOUT);
+
+  const server = createServer((editor) => {
+    clearTimeout(acceptTimeout);
+    server.close();
+    const adapter = connect({ host: adapterHost, port: Number(adapterPort) });
+    editor.pipe(adapter).pipe(editor);
+    for (const socket of [editor, adapter]) {
+      socket.on("close", stop);
+      socket.on("error", stop);
+    }
+  });
+  server.listen(0, RELAY_HOST, () => {
+    process.send({ port: server.address().port });
+    process.disconnect();
+  });
+}
+
+/**
+ * Check if the port accepts connections.
+ *
+ * @param {string} host
+ * @param {number} port
+ * @returns {Promise<boolean>}
+ */
+function isReachable(host, port) {
+  return new Promise((resolve) => {
+    const socket = connect({ host, port, timeout: PROBE_TIMEOUT });
+    const done = (reachable) => {
+      socket.destroy();
+      resolve(reachable);
+    };
+    socket.once("connect", () => done(true));
+    socket.once("timeout", () => done(false));
+    socket.once("error", () => done(false));
+  });
+}
+
+/**
+ * Stop the command with its children, as it's started in its own process group.
+ *
+ * @param {number} pid
+ */
+function killGroup(pid) {
+  try {
+    process.kill(process.platform === "win32" ? pid : -pid);
+  } catch {}
+}
+
+/**
+ * @param {string} message
+ */
+function fail(message) {
+  process.stderr.write(message);
+  process.exit(1);
+}
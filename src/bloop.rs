@@ -0,0 +1,38 @@
+// Build targets exported to Bloop
+//
+// Builds imported by Metals with Bloop (the default for sbt, Mill, Gradle and Maven) describe each build target
+// in `.bloop/<build target>.json`. The extension reads it for details Metals doesn't expose.
+
+use serde::Deserialize;
+use zed_extension_api::{self as zed, serde_json};
+
+const BLOOP_FOLDER: &str = ".bloop";
+
+// Build target's part of Bloop configuration file
+#[derive(Debug, Deserialize)]
+struct BloopConfig {
+    project: BloopProject,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BloopProject {
+    // Base directory of the build target
+    pub directory: String,
+    pub platform: Option<BloopPlatform>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BloopPlatform {
+    // "jvm", "js" or "native"
+    pub name: String,
+}
+
+pub fn project(worktree: &zed::Worktree, build_target: &str) -> Result<BloopProject, String> {
+    let path = format!("{BLOOP_FOLDER}/{build_target}.json");
+    let config = worktree.read_text_file(&path).map_err(|_| {
+        format!("build target `{build_target}` not found in `{BLOOP_FOLDER}` - is the build imported with Bloop?")
+    })?;
+    serde_json::from_str::<BloopConfig>(&config)
+        .map(|config| config.project)
+        .map_err(|e| format!("cannot read `{path}`: {e}"))
+}
@@ -7,24 +7,52 @@
 
 // DAP (Debug Adapter Protocol) specific implementation
 
-use std::{collections::HashMap, fs, path::Path};
+use std::{
+    collections::HashMap,
+    fs,
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+};
 
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use zed_extension_api::{
     self as zed,
     http_client::{self as http, HttpMethod, HttpRequest},
+    process::Command,
     serde_json::{self, Map, Value, json},
 };
 
+use crate::{bloop, env_file, error::ScalaError, jdwp};
+
 const LSP_REQUEST: &str = "workspace/executeCommand"; // LSP request to send a command
 const DAP_START_COMMAND: &str = "debug-adapter-start"; // The command send to LSP to initialize debugger
+const LIST_BUILD_TARGETS_COMMAND: &str = "list-build-targets"; // The command send to LSP to get names of build targets
 const PROXY_FOLDER: &str = "proxy"; // The folder (inside Zed's `extentions/work/scala` folder) to put port info to
+const PROXY_HEALTH_PATH: &str = "/health"; // The proxy's endpoint reporting its and Metals' state
+const LSP_TIMEOUT_ERROR_CODE: i64 = -32803; // Error code reported by the proxy when Metals didn't respond in time
+pub const DEFAULT_LSP_TIMEOUT: u64 = 5_000; // Default timeout (ms) of requests sent to Metals
+pub const DEFAULT_DAP_START_TIMEOUT: u64 = 120_000; // Default timeout (ms) of "debug-adapter-start", which may need to compile first
+const PACKAGE_NAME_VARIABLE: &str = "ZED_CUSTOM_scala_package_name"; // Package of a runnable, captured in `runnables.scm`
+const MAIN_NAME_VARIABLE: &str = "ZED_CUSTOM_scala_main_function_name"; // Main method or object of a runnable
+const TEST_NAME_VARIABLE: &str = "ZED_CUSTOM_scala_test_class_name"; // Test suite of a runnable
+const TEST_CASE_VARIABLE: &str = "ZED_CUSTOM_scala_test_name"; // Single test in a suite, if the runnable is a test case
+const FILE_VARIABLE: &str = "ZED_FILE"; // File of the runnable, to infer its build target
+const NO_DEBUG_VARIABLE: &str = "METALS_NO_DEBUG"; // Environment variable of generic launches and tasks to run without debugging
+const TEST_CASE_SEPARATOR: char = '#'; // Separates suite and test in generic launch program, like `Suite#test`
+const TEST_DIRECTORIES: [&str; 2] = ["test", "it"]; // Directories of test sources, as in `src/test/scala`
+const TEST_TARGET_SUFFIXES: [&str; 4] = ["-test", ".test", "-it", ".it"]; // Suffixes of test build targets
+const PLATFORM_SEPARATORS: [&str; 2] = ["-", "."]; // Separators of platform suffixes in build target names, as in `core-js`
 const DEFAULT_LAUNCH_RUN_TYPE: &str = "runOrTestFile"; // Default runType for autodiscovery debugee launch mode
 const DEFAULT_ATTACH_HOST_NAME: &str = "localhost"; // Default hostName for debugee attach mode
 const DEFAULT_ATTACH_PORT: u16 = 5005; // Default port number for debugee attach mode
+const DEFAULT_CONNECT_TIMEOUT: u64 = 30_000; // Default time (ms) to wait for the debug port in attach mode
+const DEFAULT_RETRY_INTERVAL: u64 = 250; // Default initial time (ms) between connection attempts in attach mode
+const ATTACH_HELPER_CODE: &str = include_str!("attach.mjs"); // Runs pre-attach command and waits for the debug port
 
 // Struct representing debugging configuration as required by Metals' "debug-adapter-start" command
 // See https://scalameta.org/metals/docs/integrations/debug-adapter-protocol/
+// Created once per debug session, so the size of the launch variant doesn't matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum ScalaDebugTaskDefinition {
@@ -32,6 +60,13 @@ pub enum ScalaDebugTaskDefinition {
     Attach(ScalaDebugAttachDefinition),
 }
 
+impl ScalaDebugTaskDefinition {
+    // Whether the program or tests are run without debugging
+    pub fn is_no_debug(&self) -> bool {
+        matches!(self, ScalaDebugTaskDefinition::Launch(config) if config.no_debug == Some(true))
+    }
+}
+
 // Debugging configuration for launch mode
 // For launching vs attaching see https://zed.dev/docs/debugger#launching--attaching
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -40,8 +75,12 @@ pub struct ScalaDebugLauchDefinition {
     #[serde(flatten)]
     entry: EntryPoint,
     #[serde(skip_serializing_if = "Option::is_none")]
-    #[serde(rename = "buildTaget")]
-    build_taget: Option<String>,
+    #[serde(rename = "buildTarget")]
+    build_target: Option<String>,
+    // File the configuration was created for, used to infer the build target (not passed to Metals)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "sourceFile")]
+    source_file: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     args: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -52,6 +91,10 @@ pub struct ScalaDebugLauchDefinition {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "envFile")]
     env_file: Option<String>,
+    // Run the program or tests without debugging, with the classpath of the build target
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "noDebug")]
+    no_debug: Option<bool>,
 }
 
 // Debugging configuration for attach mode
@@ -59,25 +102,48 @@ pub struct ScalaDebugLauchDefinition {
 pub struct ScalaDebugAttachDefinition {
     request: String,
     #[serde(skip_serializing_if = "Option::is_none")]
-    #[serde(rename = "buildTaget")]
-    build_taget: Option<String>,
+    #[serde(rename = "buildTarget")]
+    build_target: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "hostName")]
     host_name: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     port: Option<u16>,
+    // Command making the debug port reachable (e.g. port forwarding), run in the background before attaching
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "preAttachCommand")]
+    pre_attach_command: Option<String>,
+    // Time (ms) to wait for the debug port to become reachable
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "connectTimeout")]
+    connect_timeout: Option<u64>,
+    // Initial time (ms) between connection attempts, doubled after each one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "retryInterval")]
+    retry_interval: Option<u64>,
+}
+
+// Preparation of the debug port in attach mode, from the attach profile fields
+pub struct AttachProfile {
+    host: String,
+    port: u16,
+    command: Option<String>,
+    timeout: u64,
+    interval: u64,
 }
 
 // Debugger needs an entry point to launch a program or test.
-// There are 3 ways to provide it:
+// There are 4 ways to provide it:
 // - Auto: Automatically detect the entry point based on the provided file (path)
 // - Main: Specify the main class to run
 // - Test: Specify the test class to run
+// - TestSelection: Specify test suites, and optionally individual tests in them, to run
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(untagged)]
 enum EntryPoint {
     Auto {
         path: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
         #[serde(rename = "runType")]
         run_type: Option<String>,
     },
@@ -89,6 +155,39 @@ enum EntryPoint {
         #[serde(rename = "testClass")]
         test_class: String,
     },
+    TestSelection {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        target: Option<BuildTargetIdentifier>,
+        #[serde(rename = "requestData")]
+        request_data: TestSuites,
+    },
+}
+
+// Build target, identified by its URI, as in BSP (Build Server Protocol)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct BuildTargetIdentifier {
+    uri: String,
+}
+
+// Test suites to run, as in BSP "scala-test-suites-selection" data kind
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TestSuites {
+    suites: Vec<TestSuite>,
+    #[serde(default)]
+    jvm_options: Vec<String>,
+    // In `NAME=value` form
+    #[serde(default)]
+    environment_variables: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TestSuite {
+    class_name: String,
+    // Names of the tests to run - all tests of the suite are run if empty
+    #[serde(default)]
+    tests: Vec<String>,
 }
 
 // Struct representing response from LSP server (Metals)
@@ -106,6 +205,18 @@ struct LspError {
     data: Option<Value>,
 }
 
+// Failure of a request sent to Metals through the proxy
+enum LspRequestError {
+    TimedOut,
+    Failed(String),
+}
+
+impl From<String> for LspRequestError {
+    fn from(error: String) -> Self {
+        LspRequestError::Failed(error)
+    }
+}
+
 // Struct representing response to "debug-adapter-start" command
 #[derive(Debug, Deserialize)]
 #[serde(rename = "result")]
@@ -115,15 +226,37 @@ struct DapStartResult {
     uri: String,
 }
 
+// Struct representing response from the proxy's health endpoint
+#[derive(Debug, Deserialize)]
+struct ProxyHealth {
+    status: String,
+    #[serde(default)]
+    tasks: Vec<String>,
+}
+
+// State of the proxy and Metals behind it
+pub enum ProxyState {
+    // The proxy hasn't written its port and secret files yet
+    Starting,
+    NotRunning,
+    // The proxy didn't answer (e.g. timed out or rejected the secret), with the error
+    NotResponding(String),
+    Initializing,
+    Importing(Vec<String>),
+    Ready,
+}
+
 // Struct containing methods responsible for debugger initialization
 pub struct Debugger;
 
 impl Debugger {
-    // Starts the debugger by sending "debug-adapter-start" request to Metals
+    // Starts the debugger by sending "debug-adapter-start" request to Metals,
+    // waiting at most `timeout` milliseconds for the response
     pub fn start(
         workspace: &str,
         arguments: &ScalaDebugTaskDefinition,
-    ) -> zed::Result<zed::TcpArgumentsTemplate> {
+        timeout: u64,
+    ) -> Result<zed::TcpArgumentsTemplate, ScalaError> {
         // Send the "debug-adapter-start" request to LSP
         let response = Debugger::lsp_request::<DapStartResult>(
             workspace,
@@ -132,10 +265,16 @@ impl Debugger {
               "command": DAP_START_COMMAND,
               "arguments": [ arguments ]
             }),
-        )?;
+            timeout,
+        )
+        .map_err(|e| match e {
+            LspRequestError::TimedOut => ScalaError::DebuggerStartTimedOut(timeout),
+            LspRequestError::Failed(e) => ScalaError::DebuggerStartFailed(e),
+        })?;
 
         // Get debugger port from request
-        let port = get_port_from_uri(response.uri.as_str())?;
+        let port =
+            get_port_from_uri(response.uri.as_str()).map_err(ScalaError::DebuggerStartFailed)?;
 
         // Return TCP connection data
         Ok(zed::TcpArgumentsTemplate {
@@ -150,6 +289,19 @@ impl Debugger {
     // (see: https://zed.dev/docs/debugger#configuration), this method verifies key ones
     // and provides default values where possible.
     pub fn enrich_config(
+        worktree: &zed::Worktree,
+        debug_task_def: ScalaDebugTaskDefinition,
+    ) -> Result<ScalaDebugTaskDefinition, ScalaError> {
+        let workspace = worktree.root_path();
+        let debug_task_def = Debugger::with_defaults(&workspace, debug_task_def)
+            .and_then(|def| Debugger::with_build_target(&workspace, def))
+            .and_then(|def| Debugger::with_env_file(worktree, def))
+            .map_err(ScalaError::InvalidDebugConfig)?;
+        Debugger::check_platform(worktree, &debug_task_def)?;
+        Ok(debug_task_def)
+    }
+
+    fn with_defaults(
         workspace: &str,
         debug_task_def: ScalaDebugTaskDefinition,
     ) -> zed::Result<ScalaDebugTaskDefinition> {
@@ -159,7 +311,7 @@ impl Debugger {
                 match config.entry {
                     // For autodiscovery, prefix path with file scheme and provide default runType if missing
                     // Please note, that we cannot provide "$ZED_FILE" as default for path,
-                    // because it's evaluated only before calling extension.
+                    // because it's evaluated only before calling extension (see `variables` for the extension's own).
                     EntryPoint::Auto { path, run_type } => {
                         let path = if path.starts_with("file://") {
                             path
@@ -173,10 +325,77 @@ impl Debugger {
                         };
                         Ok(ScalaDebugTaskDefinition::Launch(config))
                     }
-                    // No defaults for mainClass
-                    EntryPoint::Main { main_class: _ } => Ok(debug_task_def),
-                    // No defaults for testClass
-                    EntryPoint::Test { test_class: _ } => Ok(debug_task_def),
+                    // No defaults for mainClass, but drop the package separator left
+                    // by the locator for classes in the default package
+                    EntryPoint::Main { main_class } => {
+                        let config = ScalaDebugLauchDefinition {
+                            entry: EntryPoint::Main {
+                                main_class: unqualify_default_package(main_class),
+                            },
+                            ..config
+                        };
+                        Ok(ScalaDebugTaskDefinition::Launch(config))
+                    }
+                    // No defaults for testClass, as above
+                    EntryPoint::Test { test_class } => {
+                        let config = ScalaDebugLauchDefinition {
+                            entry: EntryPoint::Test {
+                                test_class: unqualify_default_package(test_class),
+                            },
+                            ..config
+                        };
+                        Ok(ScalaDebugTaskDefinition::Launch(config))
+                    }
+                    EntryPoint::TestSelection {
+                        target,
+                        request_data,
+                    } => {
+                        if request_data.suites.is_empty() {
+                            return Err("No test suites to run in `requestData.suites`".to_string());
+                        }
+                        let suites = request_data
+                            .suites
+                            .into_iter()
+                            .map(|suite| TestSuite {
+                                class_name: unqualify_default_package(suite.class_name),
+                                tests: suite
+                                    .tests
+                                    .iter()
+                                    .map(|test| unquote_test_name(test))
+                                    .filter(|test| !test.is_empty())
+                                    .map(str::to_string)
+                                    .collect(),
+                            })
+                            .collect::<Vec<_>>();
+                        let config = match suites.as_slice() {
+                            // A whole suite (like one from the locator, without a test case)
+                            // is run in the simpler, testClass mode, with the selection's options
+                            [suite] if suite.tests.is_empty() && target.is_none() => {
+                                ScalaDebugLauchDefinition {
+                                    entry: EntryPoint::Test {
+                                        test_class: suite.class_name.clone(),
+                                    },
+                                    jvm_options: merge_options(
+                                        config.jvm_options,
+                                        request_data.jvm_options,
+                                    ),
+                                    env: merge_env(config.env, request_data.environment_variables),
+                                    ..config
+                                }
+                            }
+                            _ => ScalaDebugLauchDefinition {
+                                entry: EntryPoint::TestSelection {
+                                    target,
+                                    request_data: TestSuites {
+                                        suites,
+                                        ..request_data
+                                    },
+                                },
+                                ..config
+                            },
+                        };
+                        Ok(ScalaDebugTaskDefinition::Launch(config))
+                    }
                 }
             }
             // Attach mode - provide default host and port if missing
@@ -192,10 +411,282 @@ impl Debugger {
                 };
                 Ok(ScalaDebugTaskDefinition::Attach(config))
             }
-            _ => Err(format!("Incorrect format of debug task definition")),
+            _ => Err("Incorrect format of debug task definition".to_string()),
+        }
+    }
+
+    // Check the build target against the ones known to Metals, as Metals ignores an unknown one.
+    // If the target is missing, infer it from the file the configuration was created for.
+    fn with_build_target(
+        workspace: &str,
+        debug_task_def: ScalaDebugTaskDefinition,
+    ) -> zed::Result<ScalaDebugTaskDefinition> {
+        match debug_task_def {
+            ScalaDebugTaskDefinition::Launch(mut config) => {
+                let source_file = config.source_file.take();
+                config.build_target = Debugger::resolve_build_target(
+                    workspace,
+                    config.build_target,
+                    source_file.as_deref(),
+                )?;
+                Ok(ScalaDebugTaskDefinition::Launch(config))
+            }
+            ScalaDebugTaskDefinition::Attach(mut config) => {
+                config.build_target =
+                    Debugger::resolve_build_target(workspace, config.build_target, None)?;
+                Ok(ScalaDebugTaskDefinition::Attach(config))
+            }
+        }
+    }
+
+    fn resolve_build_target(
+        workspace: &str,
+        build_target: Option<String>,
+        source_file: Option<&str>,
+    ) -> zed::Result<Option<String>> {
+        if build_target.is_none() && source_file.is_none() {
+            return Ok(None);
+        }
+        // Older Metals versions don't list build targets - leave it to Metals then
+        let Ok(build_targets) = Debugger::lsp_request::<Vec<String>>(
+            workspace,
+            LSP_REQUEST,
+            json!({ "command": LIST_BUILD_TARGETS_COMMAND }),
+            DEFAULT_LSP_TIMEOUT,
+        ) else {
+            return Ok(build_target);
+        };
+
+        match (build_target, source_file) {
+            (Some(name), _) if build_targets.contains(&name) => Ok(Some(name)),
+            (Some(name), _) => Err(format!(
+                "Unknown build target `{name}`. Available build targets: {}",
+                build_targets.join(", ")
+            )),
+            (None, Some(file)) => Ok(infer_build_target(&build_targets, workspace, file)),
+            (None, None) => Ok(None),
+        }
+    }
+
+    // Load the environment file, so that its errors are reported before the debugger starts.
+    // Variables from `env` take precedence over the ones from `envFile`.
+    fn with_env_file(
+        worktree: &zed::Worktree,
+        debug_task_def: ScalaDebugTaskDefinition,
+    ) -> zed::Result<ScalaDebugTaskDefinition> {
+        let ScalaDebugTaskDefinition::Launch(mut config) = debug_task_def else {
+            return Ok(debug_task_def);
+        };
+        let Some(env_file) = config.env_file.take() else {
+            return Ok(ScalaDebugTaskDefinition::Launch(config));
+        };
+
+        // Zed gives access only to the files of the worktree, so other files (like `~/secrets/app.env`)
+        // are left to Metals to read
+        let workspace = worktree.root_path();
+        let path = full_path(&env_file, &workspace);
+        let Ok(relative) = Path::new(&path).strip_prefix(&workspace) else {
+            config.env_file = Some(env_file);
+            return Ok(ScalaDebugTaskDefinition::Launch(config));
+        };
+        let content = worktree
+            .read_text_file(&relative.to_string_lossy())
+            .map_err(|e| format!("Cannot read environment file `{path}`: {e}"))?;
+        let shell_env = worktree.shell_env().into_iter().collect();
+        let variables = env_file::parse(&content, &path, &shell_env)?;
+
+        match &mut config.entry {
+            // Environment of the tests is a part of the selection
+            EntryPoint::TestSelection { request_data, .. } => {
+                let defined = request_data
+                    .environment_variables
+                    .iter()
+                    .filter_map(|variable| variable.split_once('='))
+                    .map(|(name, _)| name.to_string())
+                    .collect::<Vec<_>>();
+                request_data.environment_variables.extend(
+                    variables
+                        .into_iter()
+                        .filter(|(name, _)| !defined.contains(name))
+                        .map(|(name, value)| format!("{name}={value}")),
+                );
+            }
+            _ => {
+                let mut env = variables.into_iter().collect::<HashMap<_, _>>();
+                env.extend(config.env.take().into_iter().flatten());
+                config.env = (!env.is_empty()).then_some(env);
+            }
+        }
+        Ok(ScalaDebugTaskDefinition::Launch(config))
+    }
+
+    // Take the attach profile fields from the configuration, as they're for the extension only.
+    // Returns them if the debug port has to be prepared (see `prepare_attach`).
+    pub fn attach_profile(debug_task_def: &mut ScalaDebugTaskDefinition) -> Option<AttachProfile> {
+        let ScalaDebugTaskDefinition::Attach(config) = debug_task_def else {
+            return None;
+        };
+        let command = config.pre_attach_command.take();
+        let timeout = config.connect_timeout.take();
+        let interval = config.retry_interval.take();
+        if command.is_none() && timeout.is_none() {
+            return None;
+        }
+        Some(AttachProfile {
+            host: config
+                .host_name
+                .clone()
+                .unwrap_or(DEFAULT_ATTACH_HOST_NAME.to_string()),
+            port: config.port.unwrap_or(DEFAULT_ATTACH_PORT),
+            command,
+            timeout: timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT),
+            interval: interval.unwrap_or(DEFAULT_RETRY_INTERVAL),
+        })
+    }
+
+    // Make sure the debug port is reachable before Metals attaches to it, once Zed connects to the started debugger:
+    // run the pre-attach command (like port forwarding to a container) and wait for the port.
+    // The command is stopped when the debug session ends, so the connection to the debugger
+    // goes through the helper's relay then, which is returned instead.
+    pub fn prepare_attach(
+        worktree: &zed::Worktree,
+        profile: AttachProfile,
+        connection: zed::TcpArguments,
+    ) -> Result<zed::TcpArguments, ScalaError> {
+        let node = zed::node_binary_path().map_err(ScalaError::NodeNotFound)?;
+        // The command runs in the worktree with its shell environment, like in a terminal,
+        // rather than with Zed's own environment (lacking e.g. the PATH of the shell on macOS)
+        let mut command = Command::new(node)
+            .args(["--input-type=module", "-e", ATTACH_HELPER_CODE])
+            .envs(worktree.shell_env())
+            .args([
+                profile.host,
+                profile.port.to_string(),
+                profile.timeout.to_string(),
+                profile.interval.to_string(),
+            ]);
+        if let Some(pre_attach_command) = &profile.command {
+            command = command.args([
+                Ipv4Addr::from(connection.host).to_string(),
+                connection.port.to_string(),
+                worktree.root_path(),
+                pre_attach_command.clone(),
+            ]);
+        }
+        let output = command.output().map_err(ScalaError::DebugPortUnreachable)?;
+        if output.status != Some(0) {
+            return Err(ScalaError::DebugPortUnreachable(
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            ));
+        }
+        if profile.command.is_none() {
+            return Ok(connection);
+        }
+
+        let relay_port = String::from_utf8_lossy(&output.stdout)
+            .trim()
+            .parse::<u16>()
+            .map_err(|e| ScalaError::DebugPortUnreachable(format!("Invalid relay port: {e}")))?;
+        Ok(zed::TcpArguments {
+            host: Ipv4Addr::LOCALHOST.to_bits(),
+            port: relay_port,
+            timeout: connection.timeout,
+        })
+    }
+
+    // Only JVM build targets can be debugged. Scala.js ones can be run without debugging.
+    // The platform is known only if the build target is given or inferred, and imported with Bloop.
+    fn check_platform(
+        worktree: &zed::Worktree,
+        debug_task_def: &ScalaDebugTaskDefinition,
+    ) -> Result<(), ScalaError> {
+        let (build_target, no_debug) = match debug_task_def {
+            ScalaDebugTaskDefinition::Launch(config) => {
+                (config.build_target.as_ref(), config.no_debug == Some(true))
+            }
+            ScalaDebugTaskDefinition::Attach(config) => (config.build_target.as_ref(), false),
+        };
+        let Some(build_target) = build_target else {
+            return Ok(());
+        };
+        match Platform::of(worktree, build_target) {
+            None | Some(Platform::Jvm) => Ok(()),
+            Some(Platform::Js) if no_debug => Ok(()),
+            Some(Platform::Js) => Err(ScalaError::ScalaJsNotDebuggable(build_target.clone())),
+            Some(Platform::Native) => {
+                Err(ScalaError::ScalaNativeNotSupported(build_target.clone()))
+            }
         }
     }
 
+    // Explain why Metals could not start the debugger, if the build target isn't known to be a JVM one,
+    // but its name suggests another platform
+    pub fn explain_start_failure(
+        worktree: &zed::Worktree,
+        debug_task_def: &ScalaDebugTaskDefinition,
+        error: ScalaError,
+    ) -> ScalaError {
+        let build_target = match debug_task_def {
+            ScalaDebugTaskDefinition::Launch(config) => config.build_target.as_ref(),
+            ScalaDebugTaskDefinition::Attach(config) => config.build_target.as_ref(),
+        };
+        let (ScalaError::DebuggerStartFailed(e), Some(build_target)) = (&error, build_target)
+        else {
+            return error;
+        };
+        if Platform::of(worktree, build_target).is_some() {
+            return error;
+        }
+        let platform = match Platform::guess(build_target) {
+            Platform::Jvm => return error,
+            Platform::Js => "Scala.js",
+            Platform::Native => "Scala Native",
+        };
+        ScalaError::DebuggerStartFailed(format!(
+            "{e}. Judging by its name, build target `{build_target}` may be compiled with {platform}, which Metals cannot debug"
+        ))
+    }
+
+    // Create debug task definition for a task run from a runnable (see `runnables.scm`),
+    // if the task runs a main class or a test suite - determined by its command, like `sbt run` or `scala-cli test`.
+    // The class name is made of the runnable's task variables, which Zed substitutes before starting the debugger.
+    pub fn locate(task: &zed::TaskTemplate) -> Option<ScalaDebugTaskDefinition> {
+        let class_name = |name_variable: &str| {
+            // The package is missing for classes in the default package, see `enrich_config`
+            format!("${{{PACKAGE_NAME_VARIABLE}:}}.${{{name_variable}}}")
+        };
+        let entry = match runnable_task_kind(task)? {
+            RunnableTaskKind::Run => EntryPoint::Main {
+                main_class: class_name(MAIN_NAME_VARIABLE),
+            },
+            // The test is missing, if the runnable is a whole suite
+            RunnableTaskKind::Test => EntryPoint::TestSelection {
+                target: None,
+                request_data: TestSuites {
+                    suites: vec![TestSuite {
+                        class_name: class_name(TEST_NAME_VARIABLE),
+                        tests: vec![format!("${{{TEST_CASE_VARIABLE}:}}")],
+                    }],
+                    jvm_options: vec![],
+                    environment_variables: vec![],
+                },
+            },
+        };
+        Some(ScalaDebugTaskDefinition::Launch(
+            ScalaDebugLauchDefinition {
+                request: "launch".to_string(),
+                entry,
+                build_target: None,
+                source_file: Some(format!("${FILE_VARIABLE}")),
+                args: None,
+                jvm_options: None,
+                env: None,
+                env_file: None,
+                no_debug: is_no_debug_task(task).then_some(true),
+            },
+        ))
+    }
+
     // Create basic Metals' specific debug task definition based on general Zed's debug task.
     // Leave optional arguments empty to be enriched with default values.
     pub fn convert_generic_config(
@@ -203,18 +694,58 @@ impl Debugger {
     ) -> zed::Result<ScalaDebugTaskDefinition> {
         match generic_config.request {
             // For lauch request start DAP in autodiscover mode
-            zed::DebugRequest::Launch(launch_request) => {
-                let entry = EntryPoint::Auto {
-                    path: launch_request
-                        .cwd
-                        .map(|cwd| full_path(&launch_request.program, &cwd))
-                        .unwrap_or(launch_request.program),
-                    run_type: None,
-                };
+            zed::DebugRequest::Launch(mut launch_request) => {
+                // The environment variable turns debugging off, as there's no such option in the launch UI
+                let no_debug = launch_request
+                    .envs
+                    .iter()
+                    .position(|(name, _)| name == NO_DEBUG_VARIABLE)
+                    .map(|index| launch_request.envs.remove(index).1 == "true")
+                    .filter(|no_debug| *no_debug);
+                // A program like `com.example.MySuite#my test` runs a single test
+                if let Some((suite, test)) = test_case(&launch_request.program) {
+                    let entry = EntryPoint::TestSelection {
+                        target: None,
+                        request_data: TestSuites {
+                            suites: vec![TestSuite {
+                                class_name: suite.to_string(),
+                                tests: vec![test.to_string()],
+                            }],
+                            jvm_options: vec![],
+                            environment_variables: launch_request
+                                .envs
+                                .into_iter()
+                                .map(|(name, value)| format!("{name}={value}"))
+                                .collect(),
+                        },
+                    };
+                    return Ok(ScalaDebugTaskDefinition::Launch(
+                        ScalaDebugLauchDefinition {
+                            request: "launch".to_string(),
+                            entry,
+                            build_target: None,
+                            source_file: None,
+                            args: None,
+                            jvm_options: None,
+                            env: None,
+                            env_file: None,
+                            no_debug,
+                        },
+                    ));
+                }
+                let path = launch_request
+                    .cwd
+                    .map(|cwd| full_path(&launch_request.program, &cwd))
+                    .unwrap_or(launch_request.program);
                 let config = ScalaDebugLauchDefinition {
                     request: "launch".to_string(),
-                    entry,
-                    build_taget: None,
+                    entry: EntryPoint::Auto {
+                        path: path.clone(),
+                        run_type: None,
+                    },
+                    build_target: None,
+                    // The program is the file to run, which the build target is inferred from
+                    source_file: Some(path),
                     args: if launch_request.args.is_empty() {
                         None
                     } else {
@@ -227,44 +758,92 @@ impl Debugger {
                         Some(launch_request.envs.into_iter().collect())
                     },
                     env_file: None,
+                    no_debug,
                 };
                 Ok(ScalaDebugTaskDefinition::Launch(config))
             }
-            // Metals don't support attaching to a process by ID
-            zed::DebugRequest::Attach(_attach_request) => {
-                Err("Scala DAP doesn't support attaching to a process by id.
-                  Provide debug task definition with a debug port to attach to a running program"
-                    .to_string())
+            // Metals attaches only through a debug port, so find it in the process' JVM options
+            zed::DebugRequest::Attach(attach_request) => {
+                let pid = attach_request
+                    .process_id
+                    .ok_or("Select a process to attach to".to_string())?;
+                let (host_name, port) = jdwp::debug_address(pid)?;
+                Ok(ScalaDebugTaskDefinition::Attach(
+                    ScalaDebugAttachDefinition {
+                        request: "attach".to_string(),
+                        build_target: None,
+                        host_name: Some(host_name),
+                        port: Some(port),
+                        pre_attach_command: None,
+                        connect_timeout: None,
+                        retry_interval: None,
+                    },
+                ))
             }
         }
     }
 
-    // Send request to Metals through the proxy-exposed HTTP port
-    fn lsp_request<T>(workspace: &str, method: &str, params: Value) -> zed::Result<T>
+    // Checks the state of the proxy (and Metals behind it) for the workspace through its health endpoint.
+    // Port and secret files left by a proxy which is not running anymore (the connection is refused)
+    // are removed, so the next start is clean. Other errors leave them, as the proxy may still be alive.
+    // The files are written once the proxy listens, so until then it's reported as starting.
+    pub fn probe(workspace: &str) -> ProxyState {
+        let Ok(port) = proxy_port(workspace) else {
+            return ProxyState::Starting;
+        };
+        let Ok(secret) = proxy_secret(workspace) else {
+            return ProxyState::Starting;
+        };
+
+        let response = HttpRequest::builder()
+            .method(HttpMethod::Get)
+            .url(format!("http://localhost:{port}{PROXY_HEALTH_PATH}"))
+            .header("Authorization", format!("Bearer {secret}"))
+            .build()
+            .and_then(|request| http::fetch(&request));
+        let response = match response {
+            Ok(response) => response,
+            Err(e) if is_connection_refused(&e) => {
+                let _ = fs::remove_file(port_file_path(workspace));
+                let _ = fs::remove_file(secret_file_path(workspace));
+                return ProxyState::NotRunning;
+            }
+            Err(e) => return ProxyState::NotResponding(e),
+        };
+
+        match serde_json::from_slice::<ProxyHealth>(&response.body) {
+            Ok(health) if health.status == "initializing" => ProxyState::Initializing,
+            Ok(health) if health.status == "importing" => ProxyState::Importing(health.tasks),
+            // The proxy responds, so let Metals report any other problem
+            _ => ProxyState::Ready,
+        }
+    }
+
+    // Send request to Metals through the proxy-exposed HTTP port.
+    // The proxy gives up waiting for Metals' response after `timeout` milliseconds.
+    fn lsp_request<T>(
+        workspace: &str,
+        method: &str,
+        params: Value,
+        timeout: u64,
+    ) -> Result<T, LspRequestError>
     where
         T: DeserializeOwned,
     {
         // Get the port number saved by proxy
         // We cannot cache it because the user may restart the LSP
-        let port = {
-            let filename = string_to_hex(workspace);
-            let port_path = Path::new(PROXY_FOLDER).join(filename);
-            if !fs::metadata(&port_path).is_ok_and(|file| file.is_file()) {
-                return Err("Failed to find LSP port file".to_string());
-            }
-
-            fs::read_to_string(port_path)
-                .map_err(|e| format!("Failed to read a LSP proxy port from file: {e}"))?
-                .parse::<u16>()
-                .map_err(|e| format!("Failed to read a LSP proxy port, file corrupted: {e}"))?
-        };
+        let port = proxy_port(workspace)?;
+        // The proxy rejects requests without the secret of its session
+        let secret = proxy_secret(workspace)?;
 
         let mut body = Map::new();
         body.insert("method".to_string(), Value::String(method.to_string()));
         body.insert("params".to_string(), params);
+        body.insert("timeout".to_string(), Value::from(timeout));
         let request = &HttpRequest::builder()
             .method(HttpMethod::Post)
             .url(format!("http://localhost:{port}"))
+            .header("Authorization", format!("Bearer {secret}"))
             .body(Value::Object(body).to_string())
             .build()?;
 
@@ -274,16 +853,234 @@ impl Debugger {
             .map_err(|e| format!("Failed to parse response from LSP proxy {e}"))?;
         match data {
             LspResponse::Success { result } => Ok(result),
-            LspResponse::Error { error } => Err(format!(
+            LspResponse::Error { error } if error.code == LSP_TIMEOUT_ERROR_CODE => {
+                Err(LspRequestError::TimedOut)
+            }
+            LspResponse::Error { error } => Err(LspRequestError::Failed(format!(
                 "{} {} {}",
                 error.code,
                 error.message,
                 error.data.map(|v| v.to_string()).unwrap_or(String::new())
-            )),
+            ))),
+        }
+    }
+}
+
+// Platform the build target is compiled for
+#[derive(Debug, PartialEq)]
+enum Platform {
+    Jvm,
+    Js,
+    Native,
+}
+
+impl Platform {
+    // Read the platform from Bloop's configuration, if the build is imported with Bloop
+    fn of(worktree: &zed::Worktree, build_target: &str) -> Option<Platform> {
+        let platform = bloop::project(worktree, build_target)
+            .ok()
+            .and_then(|project| project.platform)?;
+        match platform.name.to_lowercase().as_str() {
+            "js" => Some(Platform::Js),
+            "native" => Some(Platform::Native),
+            _ => Some(Platform::Jvm),
+        }
+    }
+
+    // Guess the platform from the name, following cross-building conventions like `coreJS`, `core-native` or `core.js`.
+    // It's only a hint, as names of JVM build targets may look the same.
+    fn guess(build_target: &str) -> Platform {
+        let name = test_target_module(build_target).unwrap_or(build_target);
+        let has_suffix = |suffix: &str| {
+            name.ends_with(suffix)
+                || PLATFORM_SEPARATORS.iter().any(|separator| {
+                    name.to_lowercase()
+                        .ends_with(&format!("{separator}{}", suffix.to_lowercase()))
+                })
+        };
+        if has_suffix("JS") {
+            Platform::Js
+        } else if has_suffix("Native") {
+            Platform::Native
+        } else {
+            Platform::Jvm
         }
     }
 }
 
+// What a task run from a runnable does
+#[derive(Debug, PartialEq)]
+enum RunnableTaskKind {
+    Run,
+    Test,
+}
+
+// Recognize running and testing commands of sbt, Mill, scala-cli and Bloop,
+// e.g. `sbt --client 'testOnly *Suite'` or `./mill app.run`.
+// Only tasks of runnables qualify - they reference the variable with the class name, set by the runnable
+// (in the command, arguments or environment), while other tasks (like plain `sbt test`) can't be located.
+fn runnable_task_kind(task: &zed::TaskTemplate) -> Option<RunnableTaskKind> {
+    let parts = || {
+        std::iter::once(&task.command)
+            .chain(&task.args)
+            .chain(task.env.iter().map(|(_, value)| value))
+    };
+    let kind = parts()
+        .flat_map(|part| part.split_whitespace())
+        .map(|word| word.trim_matches(['\'', '"']))
+        .map(|word| word.rsplit('.').next().unwrap_or(word))
+        .find_map(|word| match word {
+            "run" | "runMain" | "bgRun" | "bgRunMain" => Some(RunnableTaskKind::Run),
+            "test" | "testOnly" | "testQuick" => Some(RunnableTaskKind::Test),
+            _ => None,
+        })?;
+    let name_variable = match kind {
+        RunnableTaskKind::Run => MAIN_NAME_VARIABLE,
+        RunnableTaskKind::Test => TEST_NAME_VARIABLE,
+    };
+    parts()
+        .any(|part| part.contains(name_variable))
+        .then_some(kind)
+}
+
+// Tasks setting `METALS_NO_DEBUG=true` are run with Metals without debugging
+fn is_no_debug_task(task: &zed::TaskTemplate) -> bool {
+    task.env
+        .iter()
+        .any(|(name, value)| name == NO_DEBUG_VARIABLE && value == "true")
+}
+
+// Guess the build target of a file from its path, e.g. `core/src/test/scala/Foo.scala` belongs to `core-test`.
+// Build target names of sbt, Mill and Bloop are derived from module directories,
+// with test targets suffixed by `-test` or `.test`.
+fn infer_build_target(build_targets: &[String], workspace: &str, file: &str) -> Option<String> {
+    let relative = Path::new(file).strip_prefix(workspace).ok()?;
+    let segments = relative
+        .parent()?
+        .components()
+        .map(|segment| segment.as_os_str().to_string_lossy().to_lowercase())
+        .collect::<Vec<_>>();
+    let is_test = segments
+        .iter()
+        .any(|segment| TEST_DIRECTORIES.contains(&segment.as_str()));
+
+    let candidates = build_targets
+        .iter()
+        .filter(|target| test_target_module(target).is_some() == is_test)
+        .collect::<Vec<_>>();
+    // The target of the innermost module directory in the path
+    let matching = candidates
+        .iter()
+        .filter_map(|target| {
+            let module = test_target_module(target).unwrap_or(target).to_lowercase();
+            segments
+                .iter()
+                .rposition(|segment| *segment == module)
+                .map(|position| (position, *target))
+        })
+        .collect::<Vec<_>>();
+    let innermost = matching.iter().map(|(position, _)| *position).max();
+    let mut best = matching
+        .into_iter()
+        .filter(|(position, _)| Some(*position) == innermost)
+        .map(|(_, target)| target);
+
+    match (best.next(), best.next(), candidates.as_slice()) {
+        (Some(target), None, _) => Some(target.clone()),
+        // Single module build
+        (None, _, [target]) => Some((*target).clone()),
+        _ => None,
+    }
+}
+
+// Module of a test build target, e.g. `core` for `core-test`
+fn test_target_module(build_target: &str) -> Option<&str> {
+    TEST_TARGET_SUFFIXES
+        .iter()
+        .find_map(|suffix| build_target.strip_suffix(suffix))
+}
+
+// Split generic launch program into a test suite and a test, unless it's a path
+fn test_case(program: &str) -> Option<(&str, &str)> {
+    if program.contains(['/', '\\']) {
+        return None;
+    }
+    program
+        .split_once(TEST_CASE_SEPARATOR)
+        .map(|(suite, test)| (suite.trim(), test.trim()))
+        .filter(|(suite, test)| !suite.is_empty() && !test.is_empty())
+}
+
+// Test names captured from the source code come as string literals
+fn unquote_test_name(test: &str) -> &str {
+    test.trim().trim_matches('"')
+}
+
+fn merge_options(options: Option<Vec<String>>, extra: Vec<String>) -> Option<Vec<String>> {
+    let options = options
+        .into_iter()
+        .flatten()
+        .chain(extra)
+        .collect::<Vec<_>>();
+    (!options.is_empty()).then_some(options)
+}
+
+// Merge environment variables in `NAME=value` form, the existing ones take precedence
+fn merge_env(
+    env: Option<HashMap<String, String>>,
+    extra: Vec<String>,
+) -> Option<HashMap<String, String>> {
+    let mut merged = extra
+        .iter()
+        .filter_map(|variable| variable.split_once('='))
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect::<HashMap<_, _>>();
+    merged.extend(env.into_iter().flatten());
+    (!merged.is_empty()).then_some(merged)
+}
+
+fn unqualify_default_package(class_name: String) -> String {
+    match class_name.strip_prefix('.') {
+        Some(name) => name.to_string(),
+        None => class_name,
+    }
+}
+
+// Read the port number of the proxy's HTTP server, saved by proxy in the dedicated file
+fn proxy_port(workspace: &str) -> zed::Result<u16> {
+    let port_path = port_file_path(workspace);
+    if !fs::metadata(&port_path).is_ok_and(|file| file.is_file()) {
+        return Err("Failed to find LSP port file".to_string());
+    }
+
+    fs::read_to_string(port_path)
+        .map_err(|e| format!("Failed to read a LSP proxy port from file: {e}"))?
+        .parse::<u16>()
+        .map_err(|e| format!("Failed to read a LSP proxy port, file corrupted: {e}"))
+}
+
+// Read the secret required by the proxy's HTTP server, saved by proxy next to the port file
+fn proxy_secret(workspace: &str) -> zed::Result<String> {
+    fs::read_to_string(secret_file_path(workspace))
+        .map(|secret| secret.trim().to_string())
+        .map_err(|e| format!("Failed to read a LSP proxy secret from file: {e}"))
+}
+
+fn port_file_path(workspace: &str) -> PathBuf {
+    Path::new(PROXY_FOLDER).join(string_to_hex(workspace))
+}
+
+fn secret_file_path(workspace: &str) -> PathBuf {
+    Path::new(PROXY_FOLDER).join(format!("{}.secret", string_to_hex(workspace)))
+}
+
+// Check if the HTTP request failed as nothing listens on the port.
+// Zed reports the OS error as a message only, e.g. "Connection refused (os error 111)" on Unix,
+// or "No connection could be made because the target machine actively refused it" on Windows.
+fn is_connection_refused(error: &str) -> bool {
+    error.to_lowercase().contains("refused")
+}
+
 // Retrieve port number from URI
 fn get_port_from_uri(s: &str) -> zed::Result<u16> {
     s.rsplit_once(':') // split the string at the last colon
@@ -315,3 +1112,71 @@ fn full_path(path: &str, base: &str) -> String {
         p_base.join(p_path).to_string_lossy().to_string()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(command: &str, env: &[(&str, &str)]) -> zed::TaskTemplate {
+        zed::TaskTemplate {
+            label: command.to_string(),
+            command: command.to_string(),
+            args: vec![],
+            env: env
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            cwd: None,
+        }
+    }
+
+    #[test]
+    fn runnable_tasks_are_located() {
+        assert_eq!(
+            runnable_task_kind(&task(
+                "sbt --client 'testOnly *$ZED_CUSTOM_scala_test_class_name'",
+                &[]
+            )),
+            Some(RunnableTaskKind::Test)
+        );
+        assert_eq!(
+            runnable_task_kind(&task(
+                "./mill app.run",
+                &[("MAIN", "${ZED_CUSTOM_scala_main_function_name:}")]
+            )),
+            Some(RunnableTaskKind::Run)
+        );
+    }
+
+    #[test]
+    fn other_tasks_are_not_located() {
+        assert_eq!(runnable_task_kind(&task("sbt --client test", &[])), None);
+        assert_eq!(runnable_task_kind(&task("sbt compile", &[])), None);
+        // The variable doesn't match the kind of the task
+        assert_eq!(
+            runnable_task_kind(&task(
+                "sbt run",
+                &[("TEST", "$ZED_CUSTOM_scala_test_class_name")]
+            )),
+            None
+        );
+    }
+
+    #[test]
+    fn no_debug_tasks_are_run() {
+        let main = ("MAIN", "${ZED_CUSTOM_scala_main_function_name:}");
+        let located = |env: &[(&str, &str)]| Debugger::locate(&task("sbt run", env)).unwrap();
+        assert!(!located(&[main]).is_no_debug());
+        assert!(located(&[main, (NO_DEBUG_VARIABLE, "true")]).is_no_debug());
+        assert!(!located(&[main, (NO_DEBUG_VARIABLE, "false")]).is_no_debug());
+    }
+
+    #[test]
+    fn platform_is_guessed_from_name() {
+        assert_eq!(Platform::guess("coreJS"), Platform::Js);
+        assert_eq!(Platform::guess("core-native-test"), Platform::Native);
+        assert_eq!(Platform::guess("core.js"), Platform::Js);
+        assert_eq!(Platform::guess("coreJVM"), Platform::Jvm);
+        assert_eq!(Platform::guess("json"), Platform::Jvm);
+    }
+}
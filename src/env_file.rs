@@ -0,0 +1,236 @@
+// Environment files (`envFile` in debug configurations)
+//
+// They're read by the extension, rather than by Metals, so that errors are reported before the debugger starts.
+// The format follows dotenv conventions:
+// - `NAME=value` lines, optionally prefixed with `export`,
+// - empty lines and lines starting with `#` are skipped, as are ` #` comments after unquoted values,
+// - values may be in single quotes (taken literally) or double quotes (with `\n`, `\t`, `\"`, `\\` and `\$` escapes),
+// - `${NAME}` in unquoted and double-quoted values is replaced with the variable defined earlier in the file,
+//   or with the one from the environment, or with an empty string.
+
+use std::collections::HashMap;
+
+const EXPORT_PREFIX: &str = "export ";
+
+// Parse content of the environment file, `file` being its name used in errors
+pub fn parse(
+    content: &str,
+    file: &str,
+    env: &HashMap<String, String>,
+) -> Result<Vec<(String, String)>, String> {
+    let mut variables: Vec<(String, String)> = vec![];
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |e: &str| format!("{file}:{}: {e}", index + 1);
+
+        let line = line
+            .strip_prefix(EXPORT_PREFIX)
+            .unwrap_or(line)
+            .trim_start();
+        let (name, value) = line
+            .split_once('=')
+            .ok_or_else(|| error("expected `NAME=value`"))?;
+        let name = name.trim();
+        if !is_valid_name(name) {
+            return Err(error(&format!("invalid variable name `{name}`")));
+        }
+
+        let lookup = |name: &str| {
+            variables
+                .iter()
+                .rev()
+                .find(|(defined, _)| defined == name)
+                .map(|(_, value)| value.as_str())
+                .or(env.get(name).map(String::as_str))
+                .unwrap_or_default()
+                .to_string()
+        };
+        let value = parse_value(value.trim(), lookup).map_err(|e| error(&e))?;
+        variables.push((name.to_string(), value));
+    }
+    Ok(variables)
+}
+
+fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+fn parse_value(value: &str, lookup: impl Fn(&str) -> String) -> Result<String, String> {
+    if let Some(quoted) = value.strip_prefix('\'') {
+        let (literal, rest) = quoted
+            .split_once('\'')
+            .ok_or("missing closing single quote")?;
+        check_trailing(rest)?;
+        return Ok(literal.to_string());
+    }
+
+    if let Some(quoted) = value.strip_prefix('"') {
+        // References are expanded in the parts between escaped `\$`s, which are taken literally
+        let mut expanded = String::new();
+        let mut unescaped = String::new();
+        let mut chars = quoted.chars();
+        loop {
+            match chars.next() {
+                None => return Err("missing closing double quote".to_string()),
+                Some('"') => break,
+                Some('\\') => match chars.next() {
+                    Some('n') => unescaped.push('\n'),
+                    Some('t') => unescaped.push('\t'),
+                    Some('$') => {
+                        expanded.push_str(&expand(&unescaped, &lookup)?);
+                        expanded.push('$');
+                        unescaped.clear();
+                    }
+                    Some(c @ ('"' | '\\')) => unescaped.push(c),
+                    Some(c) => {
+                        unescaped.push('\\');
+                        unescaped.push(c);
+                    }
+                    None => return Err("missing closing double quote".to_string()),
+                },
+                Some(c) => unescaped.push(c),
+            }
+        }
+        check_trailing(chars.as_str())?;
+        expanded.push_str(&expand(&unescaped, &lookup)?);
+        return Ok(expanded);
+    }
+
+    // Unquoted values end at a comment
+    let value = match value.find(" #") {
+        Some(comment) => &value[..comment],
+        None => value,
+    };
+    expand(value.trim_end(), &lookup)
+}
+
+// Only a comment may follow a quoted value
+fn check_trailing(rest: &str) -> Result<(), String> {
+    let rest = rest.trim_start();
+    if rest.is_empty() || rest.starts_with('#') {
+        Ok(())
+    } else {
+        Err(format!("unexpected `{rest}` after quoted value"))
+    }
+}
+
+// Replace `${NAME}` references
+fn expand(value: &str, lookup: &impl Fn(&str) -> String) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        let reference = &rest[start + 2..];
+        let end = reference
+            .find('}')
+            .ok_or("missing closing `}` in variable reference")?;
+        let name = &reference[..end];
+        if !is_valid_name(name) {
+            return Err(format!("invalid variable reference `${{{name}}}`"));
+        }
+        expanded.push_str(&lookup(name));
+        rest = &reference[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_env(content: &str) -> Result<Vec<(String, String)>, String> {
+        let env = HashMap::from([("HOME".to_string(), "/home/user".to_string())]);
+        parse(content, ".env", &env)
+    }
+
+    fn variables(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn assignments_are_parsed() {
+        let content = "
+            # Comment
+            export PORT=8080
+            NAME = app # trailing comment
+            EMPTY=
+        ";
+        assert_eq!(
+            parse_env(content),
+            Ok(variables(&[
+                ("PORT", "8080"),
+                ("NAME", "app"),
+                ("EMPTY", "")
+            ]))
+        );
+    }
+
+    #[test]
+    fn quoted_values_are_unquoted() {
+        let content = r#"
+            SINGLE='${HOME} \n' # comment
+            DOUBLE="a\tb \"c\" \${HOME}"
+            HASH="a #b"
+        "#;
+        assert_eq!(
+            parse_env(content),
+            Ok(variables(&[
+                ("SINGLE", "${HOME} \\n"),
+                ("DOUBLE", "a\tb \"c\" ${HOME}"),
+                ("HASH", "a #b"),
+            ]))
+        );
+    }
+
+    #[test]
+    fn references_are_expanded() {
+        let content = "
+            DIR=${HOME}/app
+            LOGS=\"${DIR}/logs\"
+            MISSING=${UNDEFINED}
+        ";
+        assert_eq!(
+            parse_env(content),
+            Ok(variables(&[
+                ("DIR", "/home/user/app"),
+                ("LOGS", "/home/user/app/logs"),
+                ("MISSING", ""),
+            ]))
+        );
+    }
+
+    #[test]
+    fn errors_are_reported_with_line_numbers() {
+        assert_eq!(
+            parse_env("A=1\nINVALID"),
+            Err(".env:2: expected `NAME=value`".to_string())
+        );
+        assert_eq!(
+            parse_env("1A=1"),
+            Err(".env:1: invalid variable name `1A`".to_string())
+        );
+        assert_eq!(
+            parse_env("A=\"open"),
+            Err(".env:1: missing closing double quote".to_string())
+        );
+        assert_eq!(
+            parse_env("A='a' b"),
+            Err(".env:1: unexpected `b` after quoted value".to_string())
+        );
+        assert_eq!(
+            parse_env("A=${B"),
+            Err(".env:1: missing closing `}` in variable reference".to_string())
+        );
+    }
+}
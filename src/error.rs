@@ -0,0 +1,183 @@
+// Errors reported by the extension
+//
+// Each error carries a hint on how to fix it, so that the message shown in Zed
+// (language server status or debugger error) tells users why Metals or the debugger didn't start.
+
+use std::fmt;
+
+#[derive(Debug)]
+pub enum ScalaError {
+    // The settings in `lsp.metals` couldn't be parsed
+    InvalidSettings(String),
+    // The Metals binary configured in `lsp.metals.binary.path` doesn't exist
+    MetalsBinaryNotFound(String),
+    // Metals couldn't be installed by the extension
+    MetalsInstallFailed(String),
+    // No JDK was found, with the list of searched places
+    JavaNotFound(Vec<String>),
+    // Zed couldn't provide Node.js to run the proxy
+    NodeNotFound(String),
+    // The extension's work directory is not accessible
+    ExtensionDirUnavailable(String),
+    // The lock guarding the list of initialized workspaces is poisoned
+    WorkspaceLockPoisoned(String),
+    // DAP is requested for other adapter than Metals
+    UnsupportedAdapter(String),
+    // DAP is not supported without the proxy
+    DapNotSupported,
+    // DAP can't start, as `lsp.metals.binary.arguments` disables the proxy
+    ProxyDisabledByArguments,
+    // DAP is requested before Metals has been started for the workspace
+    LspNotStarted(String),
+    // Metals has been started for the workspace, but its proxy doesn't respond anymore
+    LspNotRunning(String),
+    // Metals' proxy is running for the workspace, but didn't answer, with the error
+    LspNotResponding(String, String),
+    // Metals' proxy is starting or running, but Metals hasn't been initialized yet
+    LspNotInitialized(String),
+    // Metals is busy importing the build, with the list of running tasks
+    MetalsBusy(Vec<String>),
+    // The debug task definition is invalid
+    InvalidDebugConfig(String),
+    // The build target is compiled to JavaScript, which can be run, but not debugged
+    ScalaJsNotDebuggable(String),
+    // The build target is compiled to native code, which Metals can't run
+    ScalaNativeNotSupported(String),
+    // The debug port to attach to didn't become reachable
+    DebugPortUnreachable(String),
+    // Metals didn't start the debugger within the timeout (in milliseconds)
+    DebuggerStartTimedOut(u64),
+    // Metals couldn't start the debugger
+    DebuggerStartFailed(String),
+}
+
+impl ScalaError {
+    fn message(&self) -> String {
+        match self {
+            ScalaError::InvalidSettings(e) => format!("Invalid Metals settings: {e}"),
+            ScalaError::MetalsBinaryNotFound(path) => format!(
+                "Metals binary `{path}` set in `lsp.metals.binary.path` does not exist or is not executable"
+            ),
+            ScalaError::MetalsInstallFailed(e) => format!("Could not install Metals: {e}"),
+            ScalaError::JavaNotFound(searched) => format!(
+                "Could not find Java (JDK) to run Metals. Searched: {}",
+                searched.join(", ")
+            ),
+            ScalaError::NodeNotFound(e) => format!("Could not find Node.js to start Metals: {e}"),
+            ScalaError::ExtensionDirUnavailable(e) => {
+                format!("Could not access the extension's work directory: {e}")
+            }
+            ScalaError::WorkspaceLockPoisoned(e) => {
+                format!("Could not access the list of initialized workspaces: {e}")
+            }
+            ScalaError::UnsupportedAdapter(adapter) => {
+                format!("Cannot get binary for adapter \"{adapter}\"")
+            }
+            ScalaError::DapNotSupported => "DAP is not supported by Scala extension".to_string(),
+            ScalaError::ProxyDisabledByArguments => {
+                "DAP cannot start if any binary arguments for Metals are set in config file"
+                    .to_string()
+            }
+            ScalaError::LspNotStarted(workspace) => format!(
+                "The Metals LSP server hasn't been started yet for the current workspace {workspace}"
+            ),
+            ScalaError::LspNotRunning(workspace) => format!(
+                "The Metals LSP server is not running anymore for the current workspace {workspace}"
+            ),
+            ScalaError::LspNotResponding(workspace, e) => format!(
+                "The Metals LSP server did not respond for the current workspace {workspace}: {e}"
+            ),
+            ScalaError::LspNotInitialized(workspace) => format!(
+                "The Metals LSP server is starting, and hasn't been initialized yet for the current workspace {workspace}"
+            ),
+            ScalaError::MetalsBusy(tasks) => format!("Metals is busy: {}", tasks.join(", ")),
+            ScalaError::InvalidDebugConfig(e) => format!("Invalid debug configuration: {e}"),
+            ScalaError::ScalaJsNotDebuggable(build_target) => {
+                format!(
+                    "Build target `{build_target}` is compiled with Scala.js, which cannot be debugged"
+                )
+            }
+            ScalaError::ScalaNativeNotSupported(build_target) => format!(
+                "Build target `{build_target}` is compiled with Scala Native, which cannot be run or debugged by Metals"
+            ),
+            ScalaError::DebugPortUnreachable(e) => format!("Cannot attach to the debug port: {e}"),
+            ScalaError::DebuggerStartTimedOut(timeout) => {
+                format!("Metals did not start debugger within {timeout}ms")
+            }
+            ScalaError::DebuggerStartFailed(e) => format!("Metals could not start debugger: {e}"),
+        }
+    }
+
+    // Remediation hint shown to the user together with the message
+    fn hint(&self) -> &'static str {
+        match self {
+            ScalaError::InvalidSettings(_) => "Fix `lsp.metals` section in your Zed settings.",
+            ScalaError::MetalsBinaryNotFound(_) => {
+                "Fix the path, or remove `lsp.metals.binary.path` to let the extension install Metals."
+            }
+            ScalaError::MetalsInstallFailed(_) => {
+                "Check your network connection, or install Metals manually with `cs install metals` and make sure it's on the PATH."
+            }
+            ScalaError::JavaNotFound(_) => {
+                "Install a JDK (e.g. `cs java --jvm 17 --setup`), or set `lsp.metals.settings.javaHome`."
+            }
+            ScalaError::NodeNotFound(_) => {
+                "Node.js runs the Metals proxy. Check `node` in your Zed settings, or restart Zed to let it install Node.js."
+            }
+            ScalaError::ExtensionDirUnavailable(_) => "Reinstall the Scala extension.",
+            ScalaError::WorkspaceLockPoisoned(_) => "Restart Zed.",
+            ScalaError::UnsupportedAdapter(_) => "Use \"Metals\" adapter for Scala.",
+            ScalaError::DapNotSupported => {
+                "Debugging requires the Metals proxy, which is disabled."
+            }
+            ScalaError::ProxyDisabledByArguments => {
+                "Move server properties from `lsp.metals.binary.arguments` to `lsp.metals.settings.serverProperties` and restart Metals."
+            }
+            ScalaError::LspNotStarted(_) => {
+                "Open a Scala file to start Metals, wait until the build is imported, and start debugging again."
+            }
+            ScalaError::LspNotRunning(_) => {
+                "Metals may have crashed or been stopped. Restart it with `editor: restart language server`, and start debugging again."
+            }
+            ScalaError::LspNotResponding(..) => {
+                "Metals may be busy. Start debugging again, or restart Metals with `editor: restart language server` if the problem persists."
+            }
+            ScalaError::LspNotInitialized(_) => {
+                "Wait until Metals finishes starting, and start debugging again."
+            }
+            ScalaError::MetalsBusy(_) => {
+                "Wait until Metals finishes importing the build, and start debugging again."
+            }
+            ScalaError::InvalidDebugConfig(_) => {
+                "See `debug_adapter_schemas/Metals.json` in the Scala extension for the valid configuration."
+            }
+            ScalaError::ScalaJsNotDebuggable(_) => {
+                "Set `\"noDebug\": true` to run it without debugging, or set `buildTarget` to a JVM build target to debug."
+            }
+            ScalaError::ScalaNativeNotSupported(_) => {
+                "Set `buildTarget` to a JVM build target of the project to debug the shared code."
+            }
+            ScalaError::DebugPortUnreachable(_) => {
+                "Check `preAttachCommand` and that the program listens on the debug port, or increase `connectTimeout`."
+            }
+            ScalaError::DebuggerStartTimedOut(_) => {
+                "Metals compiles the code before debugging - compile the build first (e.g. with the `Metals: Cascade compile` task), or increase `lsp.metals.settings.debugAdapterStartTimeout`."
+            }
+            ScalaError::DebuggerStartFailed(_) => {
+                "Make sure the build is imported and compiles, and that the debug configuration points to an existing class or file."
+            }
+        }
+    }
+}
+
+impl fmt::Display for ScalaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}. {}", self.message(), self.hint())
+    }
+}
+
+impl From<ScalaError> for String {
+    fn from(error: ScalaError) -> Self {
+        error.to_string()
+    }
+}
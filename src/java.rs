@@ -0,0 +1,259 @@
+// Java runtime discovery
+//
+// Metals (and its Coursier launcher) needs a JDK. Zed doesn't always inherit the user's shell
+// environment, so `JAVA_HOME` may be missing. The JDK is looked up in the following places:
+// - `javaHome` in Metals settings (`lsp.metals.settings.javaHome`),
+// - `JAVA_HOME` environment variable,
+// - version files in the worktree (`.java-version`, `.sdkmanrc`, `.tool-versions`),
+//   resolved against SDKMAN, asdf, jEnv and Coursier installations,
+// - the current SDKMAN Java and the default Coursier JVM,
+// - `java` available on the PATH.
+
+use std::path::Path;
+
+use zed_extension_api::{self as zed, process::Command};
+
+use crate::{error::ScalaError, metals};
+
+const JAVA_HOME: &str = "JAVA_HOME";
+
+// Struct collecting places searched for a JDK, to report them if none is found
+struct JavaSearch<'a> {
+    worktree: &'a zed::Worktree,
+    env: &'a zed::EnvVars,
+    searched: Vec<String>,
+}
+
+// JDK found for the worktree
+pub enum JavaHome {
+    // Home directory of the JDK
+    Found(String),
+    // `java` is on the PATH, but its home directory couldn't be determined,
+    // so it's left to Metals to find it
+    OnPath,
+}
+
+// Find a JDK for the worktree
+pub fn find_java_home(
+    worktree: &zed::Worktree,
+    env: &zed::EnvVars,
+    configured: Option<&str>,
+) -> Result<JavaHome, ScalaError> {
+    let mut search = JavaSearch {
+        worktree,
+        env,
+        searched: vec![],
+    };
+    search
+        .find(configured)
+        .ok_or(ScalaError::JavaNotFound(search.searched))
+}
+
+// Set JAVA_HOME in the environment, replacing the existing one
+pub fn set_java_home(env: &mut zed::EnvVars, java_home: &str) {
+    env.retain(|(name, _)| name != JAVA_HOME);
+    env.push((JAVA_HOME.to_string(), java_home.to_string()));
+}
+
+impl JavaSearch<'_> {
+    fn find(&mut self, configured: Option<&str>) -> Option<JavaHome> {
+        if let Some(home) = configured
+            && let Some(home) = self.check("lsp.metals.settings.javaHome", home)
+        {
+            return Some(JavaHome::Found(home));
+        }
+
+        if let Some(home) = self.env_var(JAVA_HOME)
+            && let Some(home) = self.check(JAVA_HOME, &home)
+        {
+            return Some(JavaHome::Found(home));
+        }
+
+        if let Some(home) = self.version_files_java_home() {
+            return Some(JavaHome::Found(home));
+        }
+
+        if let Some(sdkman) = self.sdkman_dir() {
+            let current = format!("{sdkman}/candidates/java/current");
+            if let Some(home) = self.check("SDKMAN current Java", &current) {
+                return Some(JavaHome::Found(home));
+            }
+        }
+
+        if let Some(home) = self.coursier_java_home(None) {
+            return Some(JavaHome::Found(home));
+        }
+
+        self.searched.push("java on PATH".to_string());
+        let java = self.worktree.which("java")?;
+        Some(match self.runtime_java_home(&java) {
+            Some(home) => JavaHome::Found(home),
+            None => JavaHome::OnPath,
+        })
+    }
+
+    // Ask the JVM for its home directory, as `java` on the PATH is usually a symlink
+    // (e.g. `/usr/bin/java` managed by alternatives) or a shim of a version manager,
+    // so its parent directory is not a JDK. The extension can't resolve symlinks itself,
+    // as it has no access to the file system outside of its work directory.
+    fn runtime_java_home(&mut self, java: &str) -> Option<String> {
+        let mut command = Command::new(java)
+            .args(["-XshowSettings:properties", "-version"])
+            .envs(self.env.clone());
+        let output = command
+            .output()
+            .ok()
+            .filter(|output| output.status == Some(0))?;
+        // Settings are printed to stderr as `    java.home = <home>`
+        let home = String::from_utf8_lossy(&output.stderr)
+            .lines()
+            .find_map(|line| {
+                let (name, value) = line.split_once('=')?;
+                (name.trim() == "java.home").then(|| value.trim().to_string())
+            })?;
+        self.check(&format!("java on PATH ({java})"), &home)
+    }
+
+    // Resolve Java version requested by the worktree's version files
+    fn version_files_java_home(&mut self) -> Option<String> {
+        let versions = [
+            (".java-version", self.read_java_version()),
+            (".sdkmanrc", self.read_sdkmanrc()),
+            (".tool-versions", self.read_tool_versions()),
+        ];
+        for (file, version) in versions {
+            let Some(version) = version else {
+                continue;
+            };
+            let candidates = [
+                self.sdkman_dir()
+                    .map(|dir| format!("{dir}/candidates/java/{version}")),
+                self.asdf_dir()
+                    .map(|dir| format!("{dir}/installs/java/{version}")),
+                self.home_dir()
+                    .map(|dir| format!("{dir}/.jenv/versions/{version}")),
+            ];
+            for home in candidates.into_iter().flatten() {
+                if let Some(home) = self.check(&format!("{file} ({version})"), &home) {
+                    return Some(home);
+                }
+            }
+            if let Some(home) = self.coursier_java_home(Some(&version)) {
+                return Some(home);
+            }
+        }
+        None
+    }
+
+    // `.java-version` (jEnv and others) contains just the version
+    fn read_java_version(&self) -> Option<String> {
+        self.worktree
+            .read_text_file(".java-version")
+            .ok()
+            .and_then(|content| {
+                content
+                    .lines()
+                    .map(str::trim)
+                    .find(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(str::to_string)
+            })
+    }
+
+    // `.sdkmanrc` contains `java=<version>` line
+    fn read_sdkmanrc(&self) -> Option<String> {
+        self.worktree
+            .read_text_file(".sdkmanrc")
+            .ok()
+            .and_then(|content| {
+                content.lines().find_map(|line| {
+                    line.trim()
+                        .strip_prefix("java=")
+                        .map(|version| version.trim().to_string())
+                })
+            })
+    }
+
+    // `.tool-versions` (asdf) contains `java <version>` line
+    fn read_tool_versions(&self) -> Option<String> {
+        self.worktree
+            .read_text_file(".tool-versions")
+            .ok()
+            .and_then(|content| {
+                content.lines().find_map(|line| {
+                    let mut parts = line.split_whitespace();
+                    match (parts.next(), parts.next()) {
+                        (Some("java"), Some(version)) => Some(version.to_string()),
+                        _ => None,
+                    }
+                })
+            })
+    }
+
+    // Ask Coursier (only if it's already available) for an installed JVM, without downloading one
+    fn coursier_java_home(&mut self, version: Option<&str>) -> Option<String> {
+        let coursier = metals::available_coursier(self.worktree)?;
+        let description = match version {
+            Some(version) => format!("Coursier JVM {version}"),
+            None => "Coursier default JVM".to_string(),
+        };
+        let mut command = Command::new(coursier)
+            .arg("java-home")
+            .args(
+                version
+                    .map(|version| vec!["--jvm", version])
+                    .unwrap_or_default(),
+            )
+            .envs(self.env.clone())
+            .env("COURSIER_MODE", "offline");
+        let home = command
+            .output()
+            .ok()
+            .filter(|output| output.status == Some(0))
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+            .unwrap_or_default();
+        self.check(&description, &home)
+    }
+
+    // Check if given directory is a Java home, recording it as searched
+    fn check(&mut self, description: &str, home: &str) -> Option<String> {
+        let home = home.trim_end_matches(['/', '\\']);
+        if home.is_empty() {
+            self.searched.push(description.to_string());
+            return None;
+        }
+        self.searched.push(format!("{description} ({home})"));
+        let java = Path::new(home).join("bin").join(java_binary_name());
+        self.worktree
+            .which(&java.to_string_lossy())
+            .map(|_| home.to_string())
+    }
+
+    fn env_var(&self, name: &str) -> Option<String> {
+        self.env
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.clone())
+            .filter(|value| !value.is_empty())
+    }
+
+    fn home_dir(&self) -> Option<String> {
+        self.env_var("HOME").or_else(|| self.env_var("USERPROFILE"))
+    }
+
+    fn sdkman_dir(&self) -> Option<String> {
+        self.env_var("SDKMAN_DIR")
+            .or_else(|| self.home_dir().map(|home| format!("{home}/.sdkman")))
+    }
+
+    fn asdf_dir(&self) -> Option<String> {
+        self.env_var("ASDF_DATA_DIR")
+            .or_else(|| self.home_dir().map(|home| format!("{home}/.asdf")))
+    }
+}
+
+fn java_binary_name() -> &'static str {
+    match zed::current_platform() {
+        (zed::Os::Windows, _) => "java.exe",
+        _ => "java",
+    }
+}
@@ -0,0 +1,94 @@
+// Discovery of debug ports of local JVMs
+//
+// Metals can only attach to a JVM through its JDWP (Java Debug Wire Protocol) socket.
+// To attach to a process selected by its ID, its command line is looked up with `ps`
+// (or `jps`, where `ps` is not available), and the address is read from the JDWP agent options:
+// `-agentlib:jdwp=transport=dt_socket,server=y,suspend=n,address=*:5005` or `-Xrunjdwp:...`.
+
+use zed_extension_api::process::Command;
+
+const JDWP_AGENT_PREFIXES: [&str; 2] = ["-agentlib:jdwp=", "-Xrunjdwp:"];
+const JDWP_FLAG: &str = "-agentlib:jdwp=transport=dt_socket,server=y,suspend=n,address=*:5005"; // Suggested to users
+const LOCAL_HOST: &str = "localhost";
+
+// Host and port of the JDWP socket of the local JVM process
+pub fn debug_address(pid: u32) -> Result<(String, u16), String> {
+    let command_line = command_line(pid).ok_or(format!(
+        "Could not find the command line of process {pid}. Make sure it's a running JVM"
+    ))?;
+    let options = command_line
+        .split_whitespace()
+        .find_map(|arg| {
+            JDWP_AGENT_PREFIXES
+                .iter()
+                .find_map(|prefix| arg.strip_prefix(prefix))
+        })
+        .ok_or(format!(
+            "Process {pid} doesn't accept debugger connections. Restart it with `{JDWP_FLAG}`"
+        ))?;
+    parse_jdwp_options(options).map_err(|e| format!("Cannot attach to process {pid}: {e}"))
+}
+
+// Command line of the process, with JVM options
+fn command_line(pid: u32) -> Option<String> {
+    let pid = pid.to_string();
+    let ps = Command::new("ps")
+        .args(["-o", "args=", "-p", &pid])
+        .output()
+        .ok()
+        .filter(|output| output.status == Some(0))
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|command_line| !command_line.is_empty());
+    if ps.is_some() {
+        return ps;
+    }
+
+    // `<pid> <main class> <arguments> <JVM options>`
+    let jps = Command::new("jps").arg("-lvm").output().ok()?;
+    String::from_utf8_lossy(&jps.stdout)
+        .lines()
+        .find_map(|line| {
+            line.split_once(' ')
+                .filter(|(line_pid, _)| *line_pid == pid)
+                .map(|(_, command_line)| command_line.to_string())
+        })
+}
+
+// Parse comma separated JDWP agent options, like `transport=dt_socket,server=y,address=*:5005`
+fn parse_jdwp_options(options: &str) -> Result<(String, u16), String> {
+    let option = |name: &str| {
+        options
+            .split(',')
+            .find_map(|option| option.strip_prefix(name)?.strip_prefix('='))
+    };
+
+    if option("transport").is_some_and(|transport| transport != "dt_socket") {
+        return Err(format!(
+            "the debugger listens on a shared memory, not on a socket. Restart it with `{JDWP_FLAG}`"
+        ));
+    }
+    if option("server") != Some("y") {
+        return Err(format!(
+            "the JVM connects to a debugger rather than waiting for one. Restart it with `{JDWP_FLAG}`"
+        ));
+    }
+    let address = option("address").ok_or(format!(
+        "the debug port is not set. Restart it with `{JDWP_FLAG}`"
+    ))?;
+
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port)) => (host, port),
+        None => ("", address),
+    };
+    let host = match host {
+        // Listening on all interfaces
+        "" | "*" | "0.0.0.0" => LOCAL_HOST,
+        host => host,
+    };
+    match port.parse::<u16>() {
+        Ok(port) if port != 0 => Ok((host.to_string(), port)),
+        _ => Err(format!(
+            "the debug port `{port}` is chosen at random. Restart it with a fixed port, e.g. `{JDWP_FLAG}`"
+        )),
+    }
+}
@@ -0,0 +1,701 @@
+// Labels of completions and symbols
+//
+// Labels are rendered as Scala code, so that Zed highlights them with the Scala grammar,
+// e.g. `def map[B](f: A => B): List[B]`. Only the name is used for filtering.
+// Metals puts the signature either in the label itself (`map[B](f: A => B): List[B]`),
+// or, for clients supporting label details, in `labelDetails.detail`.
+// The owner of the member (like `List` or `scala.collection`) follows the code, dimmed.
+// For completions adding an import, the import is shown instead, like `import scala.concurrent.Future`.
+
+use zed_extension_api::{
+    self as zed,
+    lsp::{Completion, CompletionKind, Symbol, SymbolKind},
+};
+
+// Highlight of the parts that are not code
+const DIMMED: &str = "comment";
+// Separator of the origin appended by Metals to labels, like `Future - scala.concurrent`
+const ORIGIN_SEPARATOR: &str = " - ";
+const CASE_ARROW: &str = "=>";
+const EXTENSION_MARKER: &str = "(extension)";
+const EXTENSION_KEYWORD: &str = "extension";
+// Prefix of names of anonymous givens in Scala 3, like `given_Ordering_Int`
+const GIVEN_PREFIX: &str = "given_";
+// Keywords introducing definitions, in Scala 2 and Scala 3
+const DEFINITION_KEYWORDS: &[&str] = &[
+    "def",
+    "val",
+    "var",
+    "class",
+    "object",
+    "trait",
+    "enum",
+    "case",
+    "type",
+    "given",
+    "extension",
+    "package",
+];
+// Modifiers shown before the definition keywords
+const MODIFIERS: &[&str] = &[
+    "override",
+    "implicit",
+    "lazy",
+    "inline",
+    "opaque",
+    "transparent",
+    "final",
+    "sealed",
+    "abstract",
+];
+
+// `labelDetails` of the completion, sent by Metals as Zed supports them
+#[derive(Default)]
+struct LabelDetails {
+    // Signature, like `[B](f: A => B): List[B]`
+    detail: Option<String>,
+    // Origin, like `scala.collection.immutable.List`
+    description: Option<String>,
+}
+
+pub fn completion(completion: Completion) -> Option<zed::CodeLabel> {
+    let label_details = completion.label_details.map(|details| LabelDetails {
+        detail: details.detail,
+        description: details.description,
+    });
+    completion_label(
+        &completion.label,
+        label_details,
+        completion.detail,
+        completion.kind?,
+    )
+}
+
+fn completion_label(
+    label: &str,
+    label_details: Option<LabelDetails>,
+    detail: Option<String>,
+    kind: CompletionKind,
+) -> Option<zed::CodeLabel> {
+    if matches!(kind, CompletionKind::Snippet) {
+        return Some(snippet_label(label, detail.as_deref()));
+    }
+    let Some(default_keyword) = completion_keyword(&kind) else {
+        return Some(plain_label(label, detail.as_deref()));
+    };
+
+    let label = normalize(label);
+    let (label, extension) = strip_extension_marker(&label);
+    let (label, label_origin) = match label.split_once(ORIGIN_SEPARATOR) {
+        Some((label, origin)) => (label, Some(origin.to_string())),
+        None => (label, None),
+    };
+    let (name, mut signature) = split_signature(label);
+    let LabelDetails {
+        detail: details_signature,
+        description,
+    } = label_details.unwrap_or_default();
+    let detail = detail.map(|detail| normalize(&detail));
+    let description = description.map(|description| normalize(&description));
+
+    // Keywords given by Metals, like `case class` or `implicit def`, take precedence over the kind
+    let hints = [detail.as_deref(), description.as_deref()];
+    let detail_keyword = hints.into_iter().flatten().find_map(leading_keywords);
+    // Extension methods are shown as methods, with the marker dimmed,
+    // as `extension` has to be followed by the extended parameter
+    let extension = extension
+        || detail_keyword.as_deref() == Some(EXTENSION_KEYWORD)
+        || hints
+            .into_iter()
+            .flatten()
+            .any(|hint| strip_extension_marker(hint).1);
+    let detail_keyword = detail_keyword.filter(|keyword| keyword != EXTENSION_KEYWORD);
+    let keyword = if default_keyword.is_empty() {
+        // Keywords of the language
+        String::new()
+    } else if let Some(keyword) = detail_keyword.clone() {
+        keyword
+    } else if extension {
+        "def".to_string()
+    } else if name.starts_with(GIVEN_PREFIX) {
+        "given".to_string()
+    } else {
+        default_keyword.to_string()
+    };
+    // Hints that are only keywords, or only markers, are neither signatures nor origins
+    let is_marker = |hint: &String| {
+        leading_keywords(hint).is_some_and(|keywords| keywords.len() == hint.len())
+            || strip_extension_marker(hint).0.is_empty()
+    };
+    let detail = detail.filter(|detail| !is_marker(detail));
+    let description = description.filter(|description| !is_marker(description));
+
+    // Signature given separately from the label
+    if signature.is_empty() {
+        signature = details_signature
+            .as_deref()
+            .map(normalize)
+            .or_else(|| detail.clone().filter(|detail| is_signature(detail)))
+            .unwrap_or_default();
+    }
+    // Type of a value given on its own
+    if signature.is_empty()
+        && is_value(&kind)
+        && let Some(tpe) = detail
+            .as_deref()
+            .filter(|detail| !detail.is_empty() && detail_keyword.is_none())
+    {
+        signature = format!(": {tpe}");
+    }
+
+    // Anonymous givens are shown by their types, like `given Ordering[Int]`
+    let given_type = (keyword == "given")
+        .then(|| anonymous_given_type(name, &signature))
+        .flatten();
+    let (shown_name, signature) = match &given_type {
+        Some(tpe) => (tpe.as_str(), String::new()),
+        None => (name, signature),
+    };
+
+    // Metals appends the package to completions of symbols that are not imported yet
+    if let Some(package) = label_origin {
+        let import = format!("import {package}.{name}");
+        return Some(code_label(&keyword, shown_name, &signature, Some(&import)));
+    }
+    let origin = description
+        .map(|description| strip_extension_marker(&description).0.to_string())
+        .or_else(|| {
+            detail.filter(|detail| {
+                !is_signature(detail) && !signature.contains(detail) && detail_keyword.is_none()
+            })
+        })
+        .map(|origin| origin.trim().to_string())
+        .filter(|origin| !origin.is_empty() && origin != name);
+    let origin = match (extension, origin) {
+        // The extension clause, like `extension (i: Int)`, is a marker on its own
+        (true, Some(origin)) if origin.starts_with(EXTENSION_KEYWORD) => Some(origin),
+        (true, Some(origin)) => Some(format!("{EXTENSION_MARKER} {origin}")),
+        (true, None) => Some(EXTENSION_MARKER.to_string()),
+        (false, origin) => origin,
+    };
+
+    Some(code_label(
+        &keyword,
+        shown_name,
+        &signature,
+        origin.as_deref(),
+    ))
+}
+
+// Zed passes only the name and the kind of symbols, so the container (owner) of the symbol
+// and its deprecation, given by Metals in other fields, can't be shown.
+pub fn symbol(symbol: Symbol) -> Option<zed::CodeLabel> {
+    let name = symbol.name;
+    if let Some(tpe) = anonymous_given_type(&name, "") {
+        return Some(code_label("given", &tpe, "", None));
+    }
+    // Names of some definitions, like `extension (s: String)`, already start with the keyword
+    let keyword = if leading_keywords(&name).is_some() {
+        ""
+    } else if name.starts_with(GIVEN_PREFIX) {
+        "given"
+    } else {
+        match symbol.kind {
+            SymbolKind::File => "",
+            SymbolKind::Module | SymbolKind::Object => "object",
+            SymbolKind::Namespace | SymbolKind::Package => "package",
+            SymbolKind::Class | SymbolKind::Struct => "class",
+            SymbolKind::Interface => "trait",
+            SymbolKind::Enum => "enum",
+            SymbolKind::EnumMember => "case",
+            SymbolKind::TypeParameter => "type",
+            SymbolKind::Method | SymbolKind::Function | SymbolKind::Constructor => "def",
+            SymbolKind::Variable => "var",
+            SymbolKind::Property | SymbolKind::Field | SymbolKind::Constant => "val",
+            _ => "",
+        }
+    };
+    Some(code_label(keyword, &name, "", None))
+}
+
+// Keyword of the definition, or `None` for completions which are not Scala code (like files).
+// Metals reports the kinds of Scala definitions:
+// - `object` as module, `trait` as interface,
+// - `case class` as class, enum cases as enum members (the detail tells them apart),
+// - type aliases and abstract types as type parameters.
+fn completion_keyword(kind: &CompletionKind) -> Option<&'static str> {
+    let keyword = match kind {
+        CompletionKind::Method | CompletionKind::Function | CompletionKind::Operator => "def",
+        CompletionKind::Constructor | CompletionKind::Class | CompletionKind::Struct => "class",
+        CompletionKind::Interface => "trait",
+        CompletionKind::Module => "object",
+        CompletionKind::Enum => "enum",
+        CompletionKind::EnumMember => "case",
+        CompletionKind::TypeParameter => "type",
+        CompletionKind::Variable => "var",
+        CompletionKind::Field
+        | CompletionKind::Constant
+        | CompletionKind::Value
+        | CompletionKind::Property => "val",
+        CompletionKind::Keyword => "",
+        _ => return None,
+    };
+    Some(keyword)
+}
+
+// Snippets, like `match (exhaustive)`, `case Some(value) =>` or `override def toString(): String`,
+// are shown as code without placeholders, followed by the dimmed note in parentheses
+fn snippet_label(label: &str, detail: Option<&str>) -> zed::CodeLabel {
+    let label = strip_placeholders(&normalize(label)).trim().to_string();
+    let (code, note) = match label.rsplit_once(" (") {
+        Some((code, note)) if note.ends_with(')') => (code.to_string(), Some(format!("({note}"))),
+        _ => (label, None),
+    };
+    // Definitions are filtered by their names, other snippets (like case clauses) by the whole code
+    let filter_range = match leading_keywords(&code) {
+        Some(keywords) if keywords.len() < code.len() && !code.contains(CASE_ARROW) => {
+            let start = keywords.len() + 1;
+            start..start + split_signature(&code[start..]).0.len()
+        }
+        _ => 0..code.len(),
+    };
+
+    let mut spans = vec![zed::CodeLabelSpan::code_range(0..code.len())];
+    let notes = note
+        .into_iter()
+        .chain(detail.map(normalize))
+        .filter(|note| !note.is_empty() && *note != code);
+    for note in notes {
+        spans.push(zed::CodeLabelSpan::literal(
+            format!(" {note}"),
+            Some(DIMMED.to_string()),
+        ));
+    }
+    zed::CodeLabel {
+        code,
+        spans,
+        filter_range: filter_range.into(),
+    }
+}
+
+// Label of completions which are not Scala code, like files in `//> using file` directives
+fn plain_label(label: &str, detail: Option<&str>) -> zed::CodeLabel {
+    let label = normalize(label);
+    let filter_range = (0..label.len()).into();
+    let mut spans = vec![zed::CodeLabelSpan::literal(label.clone(), None)];
+    if let Some(detail) = detail
+        .map(normalize)
+        .filter(|detail| !detail.is_empty() && *detail != label)
+    {
+        spans.push(zed::CodeLabelSpan::literal(
+            format!(" {detail}"),
+            Some(DIMMED.to_string()),
+        ));
+    }
+    zed::CodeLabel {
+        code: String::new(),
+        spans,
+        filter_range,
+    }
+}
+
+// Replace snippet placeholders, like `${1:value}` with `value`, and drop tab stops, like `$0`
+fn strip_placeholders(snippet: &str) -> String {
+    let mut stripped = String::new();
+    let mut chars = snippet.chars().peekable();
+    let mut open = 0;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => stripped.extend(chars.next()),
+            '$' if chars.peek() == Some(&'{') => {
+                chars.next();
+                while chars.next_if(char::is_ascii_digit).is_some() {}
+                chars.next_if_eq(&':');
+                open += 1;
+            }
+            '$' if chars.peek().is_some_and(char::is_ascii_digit) => {
+                while chars.next_if(char::is_ascii_digit).is_some() {}
+            }
+            '}' if open > 0 => open -= 1,
+            c => stripped.push(c),
+        }
+    }
+    stripped
+}
+
+// Leading keywords of the definition, like `final case class` in `final case class Foo(a: Int)`
+fn leading_keywords(text: &str) -> Option<String> {
+    let mut end = None;
+    let mut offset = 0;
+    for word in text.split(' ') {
+        if !DEFINITION_KEYWORDS.contains(&word) && !MODIFIERS.contains(&word) {
+            break;
+        }
+        offset += word.len();
+        if DEFINITION_KEYWORDS.contains(&word) {
+            end = Some(offset);
+        }
+        offset += 1;
+    }
+    end.map(|end| text[..end].to_string())
+}
+
+// Metals marks extension methods with `(extension)`
+fn strip_extension_marker(text: &str) -> (&str, bool) {
+    match text.strip_suffix(EXTENSION_MARKER) {
+        Some(text) => (text.trim_end(), true),
+        None => (text, false),
+    }
+}
+
+// Label showing `<keyword><name><signature> <origin>`, filtered by the name
+fn code_label(keyword: &str, name: &str, signature: &str, origin: Option<&str>) -> zed::CodeLabel {
+    let keyword = if keyword.is_empty() {
+        String::new()
+    } else {
+        format!("{keyword} ")
+    };
+    let code = format!("{keyword}{name}{signature}");
+    let mut spans = vec![zed::CodeLabelSpan::code_range(0..code.len())];
+    if let Some(origin) = origin {
+        spans.push(zed::CodeLabelSpan::literal(
+            format!(" {origin}"),
+            Some(DIMMED.to_string()),
+        ));
+    }
+    zed::CodeLabel {
+        code,
+        spans,
+        filter_range: (keyword.len()..keyword.len() + name.len()).into(),
+    }
+}
+
+// Type of the anonymous given, named by the compiler after the type, like `given_Ordering_Int`.
+// The result type of the signature is preferred, as the name loses the nesting of type arguments.
+fn anonymous_given_type(name: &str, signature: &str) -> Option<String> {
+    let parts = name.strip_prefix(GIVEN_PREFIX)?;
+    if let Some(tpe) = result_type(signature) {
+        return Some(tpe.to_string());
+    }
+    let mut parts = parts.split('_').filter(|part| !part.is_empty());
+    let constructor = parts.next()?;
+    let arguments = parts.collect::<Vec<_>>();
+    if arguments.is_empty() {
+        Some(constructor.to_string())
+    } else {
+        Some(format!("{constructor}[{}]", arguments.join(", ")))
+    }
+}
+
+// Result type of the signature, like `List[B]` of `[B](f: A => B): List[B]`
+fn result_type(signature: &str) -> Option<&str> {
+    let mut depth = 0;
+    let mut result = None;
+    for (index, c) in signature.char_indices() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            ':' if depth == 0 && signature[index + 1..].starts_with(' ') => {
+                result = Some(&signature[index + 2..]);
+            }
+            _ => {}
+        }
+    }
+    result.filter(|tpe| !tpe.is_empty())
+}
+
+// Labels of long signatures may be split into multiple lines
+fn normalize(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// Split `map[B](f: A => B): List[B]` into the name and the signature.
+// Operators like `::` and `+:` are names, so the result type starts only at `: `.
+fn split_signature(label: &str) -> (&str, String) {
+    let end = [label.find('['), label.find('('), label.find(": ")]
+        .into_iter()
+        .flatten()
+        .filter(|end| *end > 0)
+        .min()
+        .unwrap_or(label.len());
+    (&label[..end], label[end..].to_string())
+}
+
+fn is_value(kind: &CompletionKind) -> bool {
+    matches!(
+        kind,
+        CompletionKind::Variable
+            | CompletionKind::Field
+            | CompletionKind::Constant
+            | CompletionKind::Value
+            | CompletionKind::Property
+    )
+}
+
+fn is_signature(detail: &str) -> bool {
+    detail.starts_with(['(', '[', ':'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The rendered text, the text used for filtering and the dimmed parts of the label
+    fn render(label: zed::CodeLabel) -> (String, String, Vec<String>) {
+        let range = label.filter_range.start as usize..label.filter_range.end as usize;
+        let text = label
+            .spans
+            .iter()
+            .map(|span| match span {
+                zed::CodeLabelSpan::CodeRange(range) => {
+                    label.code[range.start as usize..range.end as usize].to_string()
+                }
+                zed::CodeLabelSpan::Literal(literal) => literal.text.clone(),
+            })
+            .collect::<String>();
+        let dimmed = label
+            .spans
+            .iter()
+            .filter_map(|span| match span {
+                zed::CodeLabelSpan::Literal(literal)
+                    if literal.highlight_name.as_deref() == Some(DIMMED) =>
+                {
+                    Some(literal.text.trim().to_string())
+                }
+                _ => None,
+            })
+            .collect();
+        let filter = text[range].to_string();
+        (text, filter, dimmed)
+    }
+
+    fn complete(
+        label: &str,
+        detail: Option<&str>,
+        kind: CompletionKind,
+    ) -> (String, String, Vec<String>) {
+        render(
+            completion(Completion {
+                label: label.to_string(),
+                label_details: None,
+                detail: detail.map(str::to_string),
+                kind: Some(kind),
+                insert_text_format: None,
+            })
+            .unwrap(),
+        )
+    }
+
+    // Completion with `labelDetails`, where the label is only the name
+    fn complete_with_details(
+        name: &str,
+        signature: Option<&str>,
+        origin: Option<&str>,
+        kind: CompletionKind,
+    ) -> (String, String, Vec<String>) {
+        let label_details = LabelDetails {
+            detail: signature.map(str::to_string),
+            description: origin.map(str::to_string),
+        };
+        render(completion_label(name, Some(label_details), None, kind).unwrap())
+    }
+
+    fn rendered(text: &str, filter: &str, dimmed: &[&str]) -> (String, String, Vec<String>) {
+        (
+            text.to_string(),
+            filter.to_string(),
+            dimmed.iter().map(|part| part.to_string()).collect(),
+        )
+    }
+
+    #[test]
+    fn methods_show_signatures() {
+        assert_eq!(
+            complete("map[B](f: A => B): List[B]", None, CompletionKind::Method),
+            rendered("def map[B](f: A => B): List[B]", "map", &[])
+        );
+        assert_eq!(
+            complete(
+                "map",
+                Some("[B](f: A => B): List[B]"),
+                CompletionKind::Method
+            ),
+            rendered("def map[B](f: A => B): List[B]", "map", &[])
+        );
+        assert_eq!(
+            complete(
+                "::[B >: A](elem: B): List[B]",
+                None,
+                CompletionKind::Operator
+            ),
+            rendered("def ::[B >: A](elem: B): List[B]", "::", &[])
+        );
+    }
+
+    #[test]
+    fn label_details_give_signatures_and_origins() {
+        assert_eq!(
+            complete_with_details(
+                "map",
+                Some("[B](f: A => B): List[B]"),
+                Some("scala.collection.immutable.List"),
+                CompletionKind::Method
+            ),
+            rendered(
+                "def map[B](f: A => B): List[B] scala.collection.immutable.List",
+                "map",
+                &["scala.collection.immutable.List"]
+            )
+        );
+        assert_eq!(
+            complete_with_details("size", Some(": Int"), Some("List"), CompletionKind::Field),
+            rendered("val size: Int List", "size", &["List"])
+        );
+        assert_eq!(
+            complete_with_details("Person", None, Some("case class"), CompletionKind::Class),
+            rendered("case class Person", "Person", &[])
+        );
+        assert_eq!(
+            complete_with_details(
+                "incr",
+                Some(": Int"),
+                Some("(extension)"),
+                CompletionKind::Method
+            ),
+            rendered("def incr: Int (extension)", "incr", &["(extension)"])
+        );
+    }
+
+    #[test]
+    fn values_show_types() {
+        assert_eq!(
+            complete("size", Some("Int"), CompletionKind::Field),
+            rendered("val size: Int", "size", &[])
+        );
+    }
+
+    #[test]
+    fn keywords_of_details_take_precedence() {
+        assert_eq!(
+            complete("Person", Some("case class"), CompletionKind::Class),
+            rendered("case class Person", "Person", &[])
+        );
+    }
+
+    #[test]
+    fn anonymous_givens_show_types() {
+        assert_eq!(
+            complete("given_Ordering_Int", None, CompletionKind::Method),
+            rendered("given Ordering[Int]", "Ordering[Int]", &[])
+        );
+        assert_eq!(
+            complete(
+                "given_Ordering_List: Ordering[List[Int]]",
+                None,
+                CompletionKind::Field
+            ),
+            rendered("given Ordering[List[Int]]", "Ordering[List[Int]]", &[])
+        );
+        assert_eq!(
+            complete("given_Conversion_String_Int", None, CompletionKind::Method),
+            rendered(
+                "given Conversion[String, Int]",
+                "Conversion[String, Int]",
+                &[]
+            )
+        );
+    }
+
+    #[test]
+    fn extension_methods_are_methods() {
+        assert_eq!(
+            complete("incr: Int (extension)", None, CompletionKind::Method),
+            rendered("def incr: Int (extension)", "incr", &["(extension)"])
+        );
+        assert_eq!(
+            complete("incr", Some("extension (i: Int)"), CompletionKind::Method),
+            rendered(
+                "def incr extension (i: Int)",
+                "incr",
+                &["extension (i: Int)"]
+            )
+        );
+    }
+
+    #[test]
+    fn auto_imports_show_imports() {
+        assert_eq!(
+            complete("Future - scala.concurrent", None, CompletionKind::Class),
+            rendered(
+                "class Future import scala.concurrent.Future",
+                "Future",
+                &["import scala.concurrent.Future"]
+            )
+        );
+    }
+
+    #[test]
+    fn snippets_are_shown_without_placeholders() {
+        assert_eq!(
+            complete("case Some(${1:value}) => $0", None, CompletionKind::Snippet),
+            rendered("case Some(value) =>", "case Some(value) =>", &[])
+        );
+        assert_eq!(
+            complete(
+                "match (exhaustive)",
+                Some("Option[Int] (2 cases)"),
+                CompletionKind::Snippet
+            ),
+            rendered(
+                "match (exhaustive) Option[Int] (2 cases)",
+                "match",
+                &["(exhaustive)", "Option[Int] (2 cases)"]
+            )
+        );
+        assert_eq!(
+            complete(
+                "override def toString(): String",
+                None,
+                CompletionKind::Snippet
+            ),
+            rendered("override def toString(): String", "toString", &[])
+        );
+    }
+
+    #[test]
+    fn other_completions_are_plain() {
+        assert_eq!(
+            complete("Main.scala", Some("file"), CompletionKind::File),
+            rendered("Main.scala file", "Main.scala", &["file"])
+        );
+    }
+
+    #[test]
+    fn symbols_show_keywords() {
+        let label = |kind, name: &str| {
+            render(
+                symbol(Symbol {
+                    kind,
+                    name: name.to_string(),
+                })
+                .unwrap(),
+            )
+        };
+        assert_eq!(
+            label(SymbolKind::Interface, "Service"),
+            rendered("trait Service", "Service", &[])
+        );
+        assert_eq!(
+            label(SymbolKind::Module, "Config"),
+            rendered("object Config", "Config", &[])
+        );
+        assert_eq!(
+            label(SymbolKind::Class, "given_Ordering_Int"),
+            rendered("given Ordering[Int]", "Ordering[Int]", &[])
+        );
+        assert_eq!(
+            label(SymbolKind::Method, "extension (s: String)"),
+            rendered("extension (s: String)", "extension (s: String)", &[])
+        );
+    }
+}
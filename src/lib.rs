@@ -1,71 +1,154 @@
 use std::{
-    collections::HashSet,
-    env,
+    collections::HashMap,
+    fs,
     str::FromStr,
     sync::{Arc, RwLock},
 };
 
 use zed_extension_api::{
     self as zed,
-    lsp::{Completion, CompletionKind, Symbol, SymbolKind},
+    lsp::{Completion, Symbol},
     serde_json::{self, Value},
     settings::LspSettings,
 };
 
 // DAP specific methods
-use crate::dap::{Debugger, ScalaDebugTaskDefinition};
+use crate::dap::{DEFAULT_DAP_START_TIMEOUT, Debugger, ProxyState, ScalaDebugTaskDefinition};
+use crate::error::ScalaError;
+use crate::java::JavaHome;
+use crate::settings::{ExtensionSettings, ServerVersion};
 
+mod bloop;
 mod dap;
-
-const LSP_DAP_NAME: &str = "metals"; // has to be lower-case as is the Metals binary name
+mod env_file;
+mod error;
+mod java;
+mod jdwp;
+mod labels;
+mod metals;
+mod settings;
+mod validation;
+mod variables;
+
+pub(crate) const LSP_DAP_NAME: &str = "metals"; // has to be lower-case as is the Metals binary name
 // Proxy is required to send request to LSP and to be able to start the DAP server
 // Zed doesn't support sesnding requests to LSP from extensions
+const NO_DEBUG_LABEL_PREFIX: &str = "Run with Metals: ";
 const PROXY_CODE: &str = include_str!("proxy.mjs");
 const USE_PROXY: bool = true;
 
+// Metals instance started through the proxy for a worktree
+struct MetalsInstance {
+    language_server_id: zed::LanguageServerId,
+    worktree_id: u64,
+}
+
 struct ScalaExtension {
-    wrks_lock: Arc<RwLock<HashSet<String>>>, // List of initialized workspaces - set by LSP, checked by DAP
+    wrks_lock: Arc<RwLock<HashMap<String, MetalsInstance>>>, // Metals instances by workspace root - set by LSP, checked by DAP
+    cached_binary_paths: HashMap<ServerVersion, String>, // Metals installed by the extension, reused for subsequent starts
+    java_homes: HashMap<String, String>, // JDK found for each worktree - passed to Metals as `javaHome` setting
 }
 
-impl zed::Extension for ScalaExtension {
-    fn new() -> Self {
-        Self {
-            wrks_lock: Arc::new(RwLock::new(HashSet::new())),
+impl ScalaExtension {
+    // Metals found on the worktree's PATH takes precedence over the one installed by the extension,
+    // unless a specific version is requested in settings
+    fn metals_binary_path(
+        &mut self,
+        language_server_id: &zed::LanguageServerId,
+        worktree: &zed::Worktree,
+        server_version: Option<ServerVersion>,
+    ) -> Result<String, ScalaError> {
+        let server_version = match server_version {
+            Some(server_version) => server_version,
+            None => match worktree.which(LSP_DAP_NAME) {
+                Some(path) => return Ok(path),
+                None => ServerVersion::Latest,
+            },
+        };
+
+        if let Some(path) = self.cached_binary_paths.get(&server_version)
+            && fs::metadata(path).is_ok_and(|file| file.is_file())
+        {
+            return Ok(path.clone());
         }
+
+        let path = metals::managed_binary(language_server_id, worktree, &server_version)
+            .map_err(ScalaError::MetalsInstallFailed)?;
+        self.cached_binary_paths
+            .insert(server_version, path.clone());
+        Ok(path)
     }
 
-    // This method is called by Zed to start LSP
-    fn language_server_command(
+    // Command starting Metals, either through the proxy (with DAP support) or directly
+    fn metals_command(
         &mut self,
-        _language_server_id: &zed::LanguageServerId,
+        language_server_id: &zed::LanguageServerId,
         worktree: &zed::Worktree,
-    ) -> zed::Result<zed::Command> {
-        let metals_path = worktree
-            .which(LSP_DAP_NAME)
-            .ok_or_else(|| "Metals must be installed manually. Recommended way is to install coursier (https://get-coursier.io/), and then run `cs install metals`.".to_string())?;
+    ) -> Result<zed::Command, ScalaError> {
+        let extension_settings = ExtensionSettings::for_worktree(worktree)?;
+        let binary_settings = LspSettings::for_worktree(LSP_DAP_NAME, worktree)
+            .ok()
+            .and_then(|lsp_settings| lsp_settings.binary);
+
+        // User-provided environment takes precedence over the shell one
+        let mut env = worktree.shell_env();
+        if let Some(binary_env) = binary_settings
+            .as_ref()
+            .and_then(|binary| binary.env.clone())
+        {
+            env.retain(|(name, _)| !binary_env.contains_key(name));
+            env.extend(binary_env);
+        }
 
-        let bin_args_opt = LspSettings::for_worktree(LSP_DAP_NAME, worktree)
-            .map(|lsp_settings| lsp_settings.binary.and_then(|binary| binary.arguments))
-            .unwrap_or_default();
+        // Metals fails silently without Java, so make sure there is one before starting it
+        match java::find_java_home(worktree, &env, extension_settings.java_home.as_deref())? {
+            JavaHome::Found(java_home) => {
+                java::set_java_home(&mut env, &java_home);
+                self.java_homes.insert(worktree.root_path(), java_home);
+            }
+            JavaHome::OnPath => {
+                self.java_homes.remove(&worktree.root_path());
+            }
+        }
+
+        let metals_path = match binary_settings
+            .as_ref()
+            .and_then(|binary| binary.path.as_ref())
+        {
+            Some(path) => worktree
+                .which(path)
+                .ok_or_else(|| ScalaError::MetalsBinaryNotFound(path.clone()))?,
+            None => self.metals_binary_path(
+                language_server_id,
+                worktree,
+                extension_settings.server_version.clone(),
+            )?,
+        };
+
+        let bin_args_opt = binary_settings
+            .as_ref()
+            .and_then(|binary| binary.arguments.clone());
 
         // Due to https://github.com/zed-industries/zed/issues/45209 Zed ignores returned arguments
         // if any are provided in the config file, rendering proxy start and thus DAP support impossible
-        let (arguments, dap_possible) = if let Some(args) = bin_args_opt {
+        // Extra server properties should be provided through extension settings to keep DAP working
+        let (mut arguments, dap_possible) = if let Some(args) = bin_args_opt {
             (args, false)
         } else {
             // If no arguments are provided, default to enabling the HTTP server.
             (vec!["-Dmetals.http=on".to_string()], true)
         };
+        arguments.extend(extension_settings.server_arguments());
 
         if USE_PROXY & dap_possible {
             // Get extension directory to store the proxy port number in dedicated file there
-            let extension_dir = env::current_dir()
-                .map_err(|err| format!("Could not get current dir: {err}"))
+            let extension_dir = metals::work_dir()
                 .and_then(|p| {
                     p.to_str()
                         .map(|s| s.to_string())
                         .ok_or("Could not convert path to string".to_string())
-                })?;
+                })
+                .map_err(ScalaError::ExtensionDirUnavailable)?;
 
             // Provide arguments to Node to start the proxy and Metals through it
             let mut args = vec![
@@ -78,47 +161,184 @@ impl zed::Extension for ScalaExtension {
             // Add arguments for Metals to pass them through
             args.extend(arguments.to_owned());
 
-            // Add current workspace to the set of initialized workspaces (for DAP)
-            // Unfortunately, LSP isn't initialized yet, and this doesn't prevent calling the DAP too early,
-            // but there is no handler to implement it in a proper way
-            let workspace = worktree.root_path();
-            let mut workspaces = self
-                .wrks_lock
+            // Register the Metals instance for the current workspace (for DAP), replacing the previous one.
+            // LSP isn't initialized yet, so DAP checks whether the proxy is alive before using it.
+            self.wrks_lock
                 .write()
-                .map_err(|e| format!("Could not mark current workspace as initialized: {e}"))?;
-            workspaces.insert(workspace);
+                .map_err(|e| ScalaError::WorkspaceLockPoisoned(e.to_string()))?
+                .insert(
+                    worktree.root_path(),
+                    MetalsInstance {
+                        language_server_id: language_server_id.clone(),
+                        worktree_id: worktree.id(),
+                    },
+                );
 
             // Embed the task helper so the proxy can write it to `~/.metals-zed/cmd.mjs`.
-            let mut env = worktree.shell_env();
             env.push((
                 "METALS_ZED_HELPER_CODE".to_string(),
                 include_str!("metals-cmd.mjs").to_string(),
             ));
 
             Ok(zed::Command {
-                command: zed::node_binary_path()?, // Node is used to start the proxy
+                command: zed::node_binary_path().map_err(ScalaError::NodeNotFound)?, // Node is used to start the proxy
                 args,
                 env,
             })
         } else {
             // Start Metals directly, without DAP support
+            self.wrks_lock
+                .write()
+                .map_err(|e| ScalaError::WorkspaceLockPoisoned(e.to_string()))?
+                .remove(&worktree.root_path());
             Ok(zed::Command {
                 command: metals_path,
                 args: arguments,
-                env: worktree.shell_env(),
+                env,
             })
         }
     }
 
+    // Starts the debugger through Metals and returns the connection to it
+    fn debug_adapter_binary(
+        &mut self,
+        adapter_name: String,
+        config: zed::DebugTaskDefinition,
+        worktree: &zed::Worktree,
+    ) -> Result<zed::DebugAdapterBinary, ScalaError> {
+        if !adapter_name.eq_ignore_ascii_case(LSP_DAP_NAME) {
+            return Err(ScalaError::UnsupportedAdapter(adapter_name));
+        }
+
+        // Due to https://github.com/zed-industries/zed/issues/45209 Zed ignores returned arguments
+        // if any are provided in the config file, rendering proxy start and thus DAP support impossible
+        LspSettings::for_worktree(LSP_DAP_NAME, worktree)
+            .map(|lsp_settings| lsp_settings.binary.and_then(|binary| binary.arguments))
+            .unwrap_or_default()
+            .map_or(Ok(()), |_| Err(ScalaError::ProxyDisabledByArguments))?;
+
+        let workspace = worktree.root_path();
+        if USE_PROXY {
+            // Check if LSP has been started for the current workspace, and its proxy and Metals are ready,
+            // and thus is able to start DAP. Forget the instance if it has stopped.
+            let mut workspaces = self
+                .wrks_lock
+                .write()
+                .map_err(|e| ScalaError::WorkspaceLockPoisoned(e.to_string()))?;
+            match workspaces.get(&workspace) {
+                Some(instance) if instance.worktree_id != worktree.id() => {
+                    workspaces.remove(&workspace);
+                    return Err(ScalaError::LspNotStarted(workspace));
+                }
+                Some(instance) => match Debugger::probe(&workspace) {
+                    ProxyState::NotRunning => {
+                        let error = ScalaError::LspNotRunning(workspace.clone());
+                        zed::set_language_server_installation_status(
+                            &instance.language_server_id,
+                            &zed::LanguageServerInstallationStatus::Failed(error.to_string()),
+                        );
+                        workspaces.remove(&workspace);
+                        return Err(error);
+                    }
+                    ProxyState::NotResponding(e) => {
+                        return Err(ScalaError::LspNotResponding(workspace, e));
+                    }
+                    ProxyState::Starting | ProxyState::Initializing => {
+                        return Err(ScalaError::LspNotInitialized(workspace));
+                    }
+                    ProxyState::Importing(tasks) => return Err(ScalaError::MetalsBusy(tasks)),
+                    ProxyState::Ready => {}
+                },
+                None => return Err(ScalaError::LspNotStarted(workspace)),
+            }
+        } else {
+            return Err(ScalaError::DapNotSupported);
+        }
+
+        // Parse the user-provided debug configuration
+        // Please note, that "label" and "adapter", required by Zed, are stripped before passing to extension
+        let mut conf = Value::from_str(config.config.as_str())
+            .map_err(|e| ScalaError::InvalidDebugConfig(format!("Invalid JSON: {e}")))?;
+        // Expand the extension's placeholders, like `${worktreeRoot}`
+        variables::substitute(&mut conf, worktree).map_err(ScalaError::InvalidDebugConfig)?;
+        validation::validate(&conf).map_err(ScalaError::InvalidDebugConfig)?;
+        let scala_conf: ScalaDebugTaskDefinition = serde_json::from_value(conf.clone())
+            .map_err(|e| ScalaError::InvalidDebugConfig(e.to_string()))?;
+
+        // Determine debug mode (lauch or attach)
+        let request_kind = zed::Extension::dap_request_kind(self, adapter_name, conf)
+            .map_err(ScalaError::InvalidDebugConfig)?;
+        // Check and enrich debug configuration with default values
+        let mut arguments = Debugger::enrich_config(worktree, scala_conf)?;
+        // The debug port in attach mode is prepared once the debugger is started
+        let attach_profile = Debugger::attach_profile(&mut arguments);
+
+        // Return debug configuration back to Zed
+        let arguments_json = serde_json::to_string(&arguments)
+            .map_err(|e| ScalaError::InvalidDebugConfig(e.to_string()))?;
+        let request_args = zed::StartDebuggingRequestArguments {
+            request: request_kind,
+            configuration: arguments_json,
+        };
+
+        // Start the debugger with provided arguments for current workspace
+        let timeout = ExtensionSettings::for_worktree(worktree)?
+            .debug_adapter_start_timeout
+            .unwrap_or(DEFAULT_DAP_START_TIMEOUT);
+        let mut connection = Debugger::start(&workspace, &arguments, timeout)
+            .map_err(|e| Debugger::explain_start_failure(worktree, &arguments, e))
+            .and_then(|template| {
+                zed::resolve_tcp_template(template).map_err(ScalaError::DebuggerStartFailed)
+            })?;
+        // Make the debug port reachable for attach mode
+        if let Some(profile) = attach_profile {
+            connection = Debugger::prepare_attach(worktree, profile, connection)?;
+        }
+
+        // Return connection to already started debugger
+        Ok(zed::DebugAdapterBinary {
+            command: None,
+            arguments: vec![],
+            cwd: Some(workspace),
+            envs: vec![],
+            request_args,
+            connection: Some(connection),
+        })
+    }
+}
+
+impl zed::Extension for ScalaExtension {
+    fn new() -> Self {
+        Self {
+            wrks_lock: Arc::new(RwLock::new(HashMap::new())),
+            cached_binary_paths: HashMap::new(),
+            java_homes: HashMap::new(),
+        }
+    }
+
+    // This method is called by Zed to start LSP
+    // Errors are shown in Zed's language server status, together with a hint how to fix them
+    fn language_server_command(
+        &mut self,
+        language_server_id: &zed::LanguageServerId,
+        worktree: &zed::Worktree,
+    ) -> zed::Result<zed::Command> {
+        let result = self.metals_command(language_server_id, worktree);
+        let status = match &result {
+            Ok(_) => zed::LanguageServerInstallationStatus::None,
+            Err(e) => zed::LanguageServerInstallationStatus::Failed(e.to_string()),
+        };
+        zed::set_language_server_installation_status(language_server_id, &status);
+        Ok(result?)
+    }
+
     fn language_server_initialization_options(
         &mut self,
         _language_server_id: &zed::LanguageServerId,
         worktree: &zed::Worktree,
     ) -> zed::Result<Option<serde_json::Value>> {
-        let initialization_options = LspSettings::for_worktree(LSP_DAP_NAME, worktree)
-            .map(|lsp_settings| lsp_settings.initialization_options.clone());
-
-        initialization_options
+        LspSettings::for_worktree(LSP_DAP_NAME, worktree)
+            .map(|lsp_settings| lsp_settings.initialization_options.clone())
     }
 
     fn language_server_workspace_configuration(
@@ -126,11 +346,23 @@ impl zed::Extension for ScalaExtension {
         _language_server_id: &zed::LanguageServerId,
         worktree: &zed::Worktree,
     ) -> zed::Result<Option<serde_json::Value>> {
-        let settings = LspSettings::for_worktree(LSP_DAP_NAME, worktree)
+        let mut settings = LspSettings::for_worktree(LSP_DAP_NAME, worktree)
             .ok()
             .and_then(|lsp_settings| lsp_settings.settings.clone())
             .unwrap_or_default();
 
+        // Pass the JDK found by the extension unless the user has chosen one
+        if let Some(java_home) = self.java_homes.get(&worktree.root_path()) {
+            if settings.is_null() {
+                settings = serde_json::json!({});
+            }
+            if let Some(settings) = settings.as_object_mut() {
+                settings
+                    .entry("javaHome")
+                    .or_insert_with(|| Value::String(java_home.clone()));
+            }
+        }
+
         Ok(Some(serde_json::json!({
             LSP_DAP_NAME: settings
         })))
@@ -141,29 +373,7 @@ impl zed::Extension for ScalaExtension {
         _language_server_id: &zed::LanguageServerId,
         completion: Completion,
     ) -> Option<zed::CodeLabel> {
-        let prefix = match completion.kind? {
-            CompletionKind::Method | CompletionKind::Function => "def ",
-            CompletionKind::Constructor
-            | CompletionKind::Class
-            | CompletionKind::Interface
-            | CompletionKind::Module => "class ",
-            CompletionKind::Variable => "var ",
-            CompletionKind::Field
-            | CompletionKind::Constant
-            | CompletionKind::Value
-            | CompletionKind::Property => "val ",
-            CompletionKind::Enum => "enum ",
-            CompletionKind::Keyword => "",
-            _ => return None,
-        };
-        let name = completion.label.replace("  ", " ").replace("\n", "");
-        let code = format!("{prefix}{name}");
-        let code_len = code.len();
-        Some(zed::CodeLabel {
-            code,
-            spans: vec![zed::CodeLabelSpan::code_range(prefix.len()..code_len)],
-            filter_range: (0..name.len()).into(),
-        })
+        labels::completion(completion)
     }
 
     fn label_for_symbol(
@@ -171,24 +381,7 @@ impl zed::Extension for ScalaExtension {
         _language_server_id: &zed_extension_api::LanguageServerId,
         symbol: Symbol,
     ) -> Option<zed::CodeLabel> {
-        let prefix = match symbol.kind {
-            SymbolKind::Module
-            | SymbolKind::Class
-            | SymbolKind::Interface
-            | SymbolKind::Constructor => "class ",
-            SymbolKind::Method | SymbolKind::Function => "def ",
-            SymbolKind::Variable => "var ",
-            SymbolKind::Property | SymbolKind::Field | SymbolKind::Constant => "val ",
-            _ => "",
-        };
-        let name = symbol.name;
-        let code = format!("{prefix}{name}");
-        let code_len = code.len();
-        Some(zed::CodeLabel {
-            code,
-            spans: vec![zed::CodeLabelSpan::code_range(prefix.len()..code_len)],
-            filter_range: (0..name.len()).into(),
-        })
+        labels::symbol(symbol)
     }
 
     // This method is called by Zed to start debugger
@@ -201,69 +394,7 @@ impl zed::Extension for ScalaExtension {
         _user_provided_debug_adapter_path: Option<String>,
         worktree: &zed::Worktree,
     ) -> zed::Result<zed::DebugAdapterBinary> {
-        if !adapter_name.eq_ignore_ascii_case(LSP_DAP_NAME) {
-            return Err(format!("Cannot get binary for adapter \"{adapter_name}\""));
-        }
-
-        // Due to https://github.com/zed-industries/zed/issues/45209 Zed ignores returned arguments
-        // if any are provided in the config file, rendering proxy start and thus DAP support impossible
-        LspSettings::for_worktree(LSP_DAP_NAME, worktree)
-            .map(|lsp_settings| lsp_settings.binary.and_then(|binary| binary.arguments))
-            .unwrap_or_default()
-            .map_or(Ok(()), |_| {
-                Err("DAP cannot start if any binary arguments for Metals are set in config file")
-            })?;
-
-        let workspace = worktree.root_path();
-        if USE_PROXY {
-            // Check if LSP has been initialized for the current workspace and thus is able to start DAP.
-            let workspaces = self
-                .wrks_lock
-                .read()
-                .map_err(|e| format!("Could not check current workspace: {e}"))?;
-            if !workspaces.contains(&workspace) {
-                return Err(format!(
-                    "The Metals LSP server hasn't been started yet for the current workspace {workspace}. Trigger LSP initialization before debugging."
-                ));
-            }
-        } else {
-            return Err(format!("DAP is not supported by Scala extension"));
-        }
-
-        // Parse the user-provided debug configuration
-        // Please note, that "label" and "adapter", required by Zed, are stripped before passing to extension
-        let conf = Value::from_str(config.config.as_str())
-            .map_err(|e| format!("Invalid JSON configuration: {e}"))?;
-        let scala_conf: ScalaDebugTaskDefinition = serde_json::from_value(conf.clone())
-            .map_err(|e| format!("Cannot parse debug taks definition: {e}"))?;
-
-        // Determine debug mode (lauch or attach)
-        let request_kind = self.dap_request_kind(adapter_name, conf)?;
-        // Check and enrich debug configuration with default values
-        let arguments = Debugger::enrich_config(&workspace, scala_conf)?;
-
-        // Return debug configuration back to Zed
-        let arguments_json = serde_json::to_string(&arguments)
-            .map_err(|e| format!("Cannot create debug taks definition: {e}"))?;
-        let request_args = zed::StartDebuggingRequestArguments {
-            request: request_kind,
-            configuration: arguments_json,
-        };
-
-        // Start the debugger with provided arguments for current workspace
-        let connection = Some(zed::resolve_tcp_template(Debugger::start(
-            &workspace, &arguments,
-        )?)?);
-
-        // Return connection to already started debugger
-        Ok(zed::DebugAdapterBinary {
-            command: None,
-            arguments: vec![],
-            cwd: Some(workspace),
-            envs: vec![],
-            request_args,
-            connection,
-        })
+        Ok(self.debug_adapter_binary(adapter_name, config, worktree)?)
     }
 
     // This method returns debug mode (launch or attach) based on configuration provided by user
@@ -286,6 +417,35 @@ impl zed::Extension for ScalaExtension {
         }
     }
 
+    // Turns tasks of runnables (main methods, apps and test suites) into debug scenarios,
+    // so that they can be debugged, not only run, from the gutter
+    fn dap_locator_create_scenario(
+        &mut self,
+        locator_name: String,
+        build_task: zed::TaskTemplate,
+        resolved_label: String,
+        debug_adapter_name: String,
+    ) -> Option<zed::DebugScenario> {
+        if locator_name != LSP_DAP_NAME || !debug_adapter_name.eq_ignore_ascii_case(LSP_DAP_NAME) {
+            return None;
+        }
+        let scala_config = Debugger::locate(&build_task)?;
+        let config = serde_json::to_string(&scala_config).ok()?;
+        let label = if scala_config.is_no_debug() {
+            format!("{NO_DEBUG_LABEL_PREFIX}{resolved_label}")
+        } else {
+            resolved_label
+        };
+
+        Some(zed::DebugScenario {
+            label,
+            adapter: debug_adapter_name,
+            build: None,
+            config,
+            tcp_connection: None,
+        })
+    }
+
     // Method to convert a standard debug configuration, with basic user input user,
     // into a configuration required by Metals.
     fn dap_config_to_scenario(
@@ -306,8 +466,14 @@ impl zed::Extension for ScalaExtension {
             format!("Cannot create debug taks definition based on generic one: {e}")
         })?;
 
+        let label = if scala_config.is_no_debug() {
+            format!("{NO_DEBUG_LABEL_PREFIX}{}", generic_config.label)
+        } else {
+            generic_config.label
+        };
+
         Ok(zed::DebugScenario {
-            label: generic_config.label,
+            label,
             adapter: generic_config.adapter,
             build: None,
             config: arguments_json,
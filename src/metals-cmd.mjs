@@ -1,6 +1,6 @@
 // Helper installed by the Metals proxy at `~/.metals-zed/cmd.mjs`.
 // Tasks invoke it as `node $HOME/.metals-zed/cmd.mjs <metals-command>`.
-// It looks up the proxy's HTTP port for the current workspace and POSTs
+// It looks up the proxy's HTTP port and secret for the current workspace and POSTs
 // `workspace/executeCommand` to dispatch the command.
 //
 // The request is fire-and-forget: the proxy returns 202 as soon as it hands
@@ -25,18 +25,19 @@ if (!cmd) {
 // the hex matches whatever the proxy wrote, regardless of how the user opened
 // the workspace.
 const workspace = realpathSync(process.env.ZED_WORKTREE_ROOT ?? process.cwd());
-const portFile = join(
-  homedir(),
-  ".metals-zed",
-  `${Buffer.from(workspace).toString("hex")}.port`,
-);
+const proxyId = Buffer.from(workspace).toString("hex");
+const portFile = join(homedir(), ".metals-zed", `${proxyId}.port`);
+// The proxy rejects requests without the secret it wrote next to the port file
+const secretFile = join(homedir(), ".metals-zed", `${proxyId}.secret`);
 
 let port;
+let secret;
 try {
   port = Number(readFileSync(portFile, "utf8").trim());
+  secret = readFileSync(secretFile, "utf8").trim();
 } catch {
   console.error(
-    `Could not find the Metals proxy port file at ${portFile}.\n` +
+    `Could not find the Metals proxy port or secret file at ${portFile}.\n` +
       `Make sure Metals is running for this workspace (open a Scala file first), and\n` +
       `that 'lsp.metals.binary.arguments' is not set in your Zed settings - it disables\n` +
       `the proxy that these tasks rely on.`,
@@ -60,6 +61,7 @@ const req = request(
     headers: {
       "Content-Type": "application/json",
       "Content-Length": Buffer.byteLength(body),
+      Authorization: `Bearer ${secret}`,
     },
   },
   (res) => {
@@ -67,6 +69,12 @@ const req = request(
     res.on("end", () => {
       if (res.statusCode === 202) {
         console.log(`Metals: ${cmd} dispatched. Watch Zed for progress.`);
+      } else if (res.statusCode === 401) {
+        console.error(
+          `Metals: ${cmd} - the Metals proxy rejected the secret from ${secretFile}.\n` +
+            `The file may belong to a previous proxy - restart the language server.`,
+        );
+        process.exit(1);
       } else {
         console.error(`Metals: ${cmd} - unexpected response ${res.statusCode}`);
         process.exit(1);
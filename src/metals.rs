@@ -0,0 +1,287 @@
+// Metals installation managed by the extension
+//
+// When `metals` cannot be found on the worktree's PATH, or a specific version is requested in settings,
+// the extension installs it with Coursier into its own work directory (`extensions/work/scala`)
+// and reuses that copy on later starts.
+
+use std::{cmp::Ordering, env, fs, path::Path};
+
+use serde::Deserialize;
+use zed_extension_api::{
+    self as zed, DownloadedFileType, GithubReleaseOptions, LanguageServerId,
+    LanguageServerInstallationStatus,
+    http_client::{self as http, HttpMethod, HttpRequest},
+    process::Command,
+    serde_json,
+};
+
+use crate::settings::ServerVersion;
+
+const METALS_ARTIFACT: &str = "org.scalameta:metals_2.13"; // Maven coordinates of Metals, without the version
+const METALS_MAIN_CLASS: &str = "scala.meta.metals.Main";
+const METALS_LATESTS_URL: &str = "https://scalameta.org/metals/latests.json"; // Latest release and snapshot versions
+const METALS_FOLDER_PREFIX: &str = "metals-"; // Every installed version gets its own `metals-<version>` folder
+const METALS_SNAPSHOT_REPOS: [&str; 2] = [
+    "sonatype:snapshots",
+    "https://central.sonatype.com/repository/maven-snapshots",
+]; // Repositories Metals snapshots are published to
+const SNAPSHOT_SUFFIX: &str = "-SNAPSHOT";
+const METALS_JAVA_OPTS: [&str; 2] = ["-Xss4m", "-Xms100m"]; // Same defaults as metals-vscode uses
+const COURSIER_REPO: &str = "coursier/coursier"; // GitHub repository with native Coursier launchers
+const COURSIER_FOLDER: &str = "coursier"; // The folder (inside the work dir) to put downloaded Coursier to
+
+// Struct representing https://scalameta.org/metals/latests.json
+#[derive(Debug, Deserialize)]
+struct MetalsLatests {
+    release: String,
+    snapshot: String,
+}
+
+// Returns the path to a Metals launcher installed by the extension.
+// Installs the requested Metals version if it's not available yet. When the latest release
+// or snapshot can't be determined (e.g. offline), the newest previously installed copy is used.
+pub fn managed_binary(
+    language_server_id: &LanguageServerId,
+    worktree: &zed::Worktree,
+    server_version: &ServerVersion,
+) -> zed::Result<String> {
+    let version = match server_version {
+        ServerVersion::Exact(version) => version.clone(),
+        ServerVersion::Latest | ServerVersion::Snapshot => {
+            zed::set_language_server_installation_status(
+                language_server_id,
+                &LanguageServerInstallationStatus::CheckingForUpdate,
+            );
+            let snapshot = *server_version == ServerVersion::Snapshot;
+            match latest_version(snapshot) {
+                Ok(version) => version,
+                Err(e) => {
+                    // Work offline with whatever has been installed before
+                    return newest_installed(snapshot).ok_or_else(|| {
+                        format!(
+                            "Could not determine the {} Metals version and no previously installed copy was found: {e}",
+                            if snapshot { "snapshot" } else { "latest" }
+                        )
+                    });
+                }
+            }
+        }
+    };
+
+    if let Some(path) = installed(&version) {
+        return Ok(path);
+    }
+
+    zed::set_language_server_installation_status(
+        language_server_id,
+        &LanguageServerInstallationStatus::Downloading,
+    );
+    install(&version, worktree).map_err(|e| match server_version {
+        ServerVersion::Exact(_) => format!(
+            "Metals {version} was not found in the local cache and could not be installed: {e}"
+        ),
+        _ => e,
+    })
+}
+
+// Installs given Metals version with Coursier as a standalone launcher (runnable offline)
+fn install(version: &str, worktree: &zed::Worktree) -> zed::Result<String> {
+    let coursier = coursier_binary(worktree)?;
+    let launcher = work_dir()?
+        .join(format!("{METALS_FOLDER_PREFIX}{version}"))
+        .join(launcher_name());
+    let launcher = launcher
+        .to_str()
+        .ok_or("Could not convert path to string".to_string())?
+        .to_string();
+
+    let mut command = Command::new(coursier)
+        .args(["bootstrap", "--standalone"])
+        .arg(format!("{METALS_ARTIFACT}:{version}"))
+        .args(["--main-class", METALS_MAIN_CLASS])
+        .args(if is_snapshot(version) {
+            METALS_SNAPSHOT_REPOS
+                .iter()
+                .flat_map(|repo| ["--repository", repo])
+                .collect()
+        } else {
+            vec![]
+        })
+        .args(METALS_JAVA_OPTS.iter().flat_map(|opt| ["--java-opt", opt]))
+        .args(["--output", &launcher, "--force"])
+        .envs(worktree.shell_env());
+    let output = command.output()?;
+    if output.status != Some(0) {
+        return Err(format!(
+            "Coursier failed to install Metals {version}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    installed(version).ok_or(format!(
+        "Coursier finished, but Metals {version} launcher was not found at {launcher}"
+    ))
+}
+
+// Returns Coursier available on the worktree's PATH, or downloads a native launcher
+fn coursier_binary(worktree: &zed::Worktree) -> zed::Result<String> {
+    if let Some(path) = available_coursier(worktree) {
+        return Ok(path);
+    }
+
+    let binary = Path::new(COURSIER_FOLDER).join(coursier_binary_name());
+    let (asset_name, file_type) = coursier_asset()?;
+    let release = zed::latest_github_release(
+        COURSIER_REPO,
+        GithubReleaseOptions {
+            require_assets: true,
+            pre_release: false,
+        },
+    )?;
+    let asset = release
+        .assets
+        .iter()
+        .find(|asset| asset.name == asset_name)
+        .ok_or_else(|| format!("No Coursier launcher {asset_name} in {}", release.version))?;
+
+    let target = match file_type {
+        // The archive is unpacked into the folder
+        DownloadedFileType::Zip => COURSIER_FOLDER.to_string(),
+        _ => binary.to_string_lossy().to_string(),
+    };
+    zed::download_file(&asset.download_url, &target, file_type)
+        .map_err(|e| format!("Failed to download Coursier: {e}"))?;
+    zed::make_file_executable(&binary.to_string_lossy())?;
+
+    Ok(work_dir()?.join(binary).to_string_lossy().to_string())
+}
+
+// Returns Coursier available on the worktree's PATH or downloaded before, without downloading it
+pub fn available_coursier(worktree: &zed::Worktree) -> Option<String> {
+    if let Some(path) = worktree.which("cs").or_else(|| worktree.which("coursier")) {
+        return Some(path);
+    }
+
+    let binary = Path::new(COURSIER_FOLDER).join(coursier_binary_name());
+    if !fs::metadata(&binary).is_ok_and(|file| file.is_file()) {
+        return None;
+    }
+    work_dir()
+        .ok()
+        .map(|dir| dir.join(binary).to_string_lossy().to_string())
+}
+
+// Name of Coursier's native launcher asset and its archive type for the current platform
+fn coursier_asset() -> zed::Result<(String, DownloadedFileType)> {
+    let (os, arch) = zed::current_platform();
+    let arch = match arch {
+        zed::Architecture::Aarch64 => "aarch64",
+        zed::Architecture::X8664 => "x86_64",
+        zed::Architecture::X86 => return Err("Coursier is not available for x86".to_string()),
+    };
+    Ok(match os {
+        zed::Os::Mac => (
+            format!("cs-{arch}-apple-darwin.gz"),
+            DownloadedFileType::Gzip,
+        ),
+        zed::Os::Linux => (format!("cs-{arch}-pc-linux.gz"), DownloadedFileType::Gzip),
+        zed::Os::Windows => (format!("cs-{arch}-pc-win32.zip"), DownloadedFileType::Zip),
+    })
+}
+
+fn coursier_binary_name() -> String {
+    match zed::current_platform() {
+        (zed::Os::Windows, zed::Architecture::Aarch64) => "cs-aarch64-pc-win32.exe".to_string(),
+        (zed::Os::Windows, _) => "cs-x86_64-pc-win32.exe".to_string(),
+        _ => "cs".to_string(),
+    }
+}
+
+fn launcher_name() -> &'static str {
+    match zed::current_platform() {
+        (zed::Os::Windows, _) => "metals.bat",
+        _ => "metals",
+    }
+}
+
+// Fetch the latest Metals release or snapshot version
+fn latest_version(snapshot: bool) -> zed::Result<String> {
+    let request = HttpRequest::builder()
+        .method(HttpMethod::Get)
+        .url(METALS_LATESTS_URL)
+        .build()?;
+    let response =
+        http::fetch(&request).map_err(|e| format!("Failed to fetch latest Metals version: {e}"))?;
+    let latests: MetalsLatests = serde_json::from_slice(&response.body)
+        .map_err(|e| format!("Failed to parse latest Metals version: {e}"))?;
+    Ok(if snapshot {
+        latests.snapshot
+    } else {
+        latests.release
+    })
+}
+
+// Returns the absolute path to the launcher of given version, if it's installed
+fn installed(version: &str) -> Option<String> {
+    let launcher = Path::new(&format!("{METALS_FOLDER_PREFIX}{version}")).join(launcher_name());
+    if !fs::metadata(&launcher).is_ok_and(|file| file.is_file()) {
+        return None;
+    }
+    work_dir()
+        .ok()
+        .map(|dir| dir.join(launcher).to_string_lossy().to_string())
+}
+
+// Returns the absolute path to the launcher of the newest installed release or snapshot
+fn newest_installed(snapshot: bool) -> Option<String> {
+    installed_versions()
+        .into_iter()
+        .filter(|version| is_snapshot(version) == snapshot && installed(version).is_some())
+        .max_by(|a, b| compare_versions(a, b))
+        .and_then(|version| installed(&version))
+}
+
+fn installed_versions() -> Vec<String> {
+    fs::read_dir(".")
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter_map(|entry| {
+                    entry
+                        .file_name()
+                        .to_str()
+                        .and_then(|name| name.strip_prefix(METALS_FOLDER_PREFIX))
+                        .map(|version| version.to_string())
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn is_snapshot(version: &str) -> bool {
+    version.ends_with(SNAPSHOT_SUFFIX)
+}
+
+// Compare versions part by part, numerically where possible (e.g. 1.10.0 > 1.9.1)
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let parts = |v: &str| {
+        v.split(|c: char| !c.is_ascii_alphanumeric())
+            .map(|part| part.to_string())
+            .collect::<Vec<_>>()
+    };
+    for (pa, pb) in parts(a).iter().zip(parts(b).iter()) {
+        let ord = match (pa.parse::<u64>(), pb.parse::<u64>()) {
+            (Ok(na), Ok(nb)) => na.cmp(&nb),
+            _ => pa.cmp(pb),
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    parts(a).len().cmp(&parts(b).len())
+}
+
+// Extension's work directory (`extensions/work/scala`), which is the current dir of the extension
+pub fn work_dir() -> zed::Result<std::path::PathBuf> {
+    env::current_dir().map_err(|err| format!("Could not get current dir: {err}"))
+}
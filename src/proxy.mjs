@@ -9,14 +9,17 @@
 // It provides HTTP port to send commands to LSP from within an extension,
 // as Zed doesn't support such functionality yet,
 // and Scala DAP server has to be initialized by sending `debug-adapter-start` to Metals.
-// The proxy should not interfere with the communication betweend the editor and language server.
+// The proxy should not interfere with the communication betweend the editor and language server,
+// except for Metals' extensions of LSP, which Zed doesn't support and the proxy answers on its behalf.
 
 import { Buffer } from "node:buffer";
 import { spawn } from "node:child_process";
+import { randomBytes, timingSafeEqual } from "node:crypto";
 import { EventEmitter } from "node:events";
 import {
   existsSync,
   mkdirSync,
+  readFileSync,
   readdirSync,
   realpathSync,
   unlinkSync,
@@ -25,6 +28,7 @@ import {
 import { createServer } from "node:http";
 import { homedir, tmpdir } from "node:os";
 import { dirname, join } from "node:path";
+import { pathToFileURL } from "node:url";
 import { Transform } from "node:stream";
 import { text } from "node:stream/consumers";
 
@@ -33,7 +37,33 @@ const HEADER_SEPARATOR = Buffer.from("\r\n", "ascii");
 const CONTENT_SEPARATOR = Buffer.from("\r\n\r\n", "ascii");
 const NAME_VALUE_SEPARATOR = Buffer.from(": ", "ascii");
 const LENGTH_HEADER = "Content-Length";
-const TIMEOUT = 5_000;
+const TIMEOUT = 5_000; // Default, if the request doesn't specify its own timeout
+const TIMEOUT_ERROR_CODE = -32803;
+const HEALTH_PATH = "/health";
+const WARNING_MESSAGE_TYPE = 2;
+const INFO_MESSAGE_TYPE = 3;
+// Titles of Metals' progress tasks, during which it's not able to start debugging
+const IMPORT_TASKS = /import|bloopInstall|connecting to build server|indexing/i;
+// Client capabilities advertised to Metals, as only the ones handled by the proxy are available.
+// Options set by the user in `lsp.metals.initialization_options` take precedence.
+// See: https://scalameta.org/metals/docs/integrations/new-editor#initializationoptions
+const CLIENT_OPTIONS = {
+  executeClientCommandProvider: true,
+  quickPickProvider: true,
+  inputBoxProvider: true,
+  doctorProvider: "json",
+  decorationProvider: false,
+  inlineDecorationProvider: false,
+  treeViewProvider: false,
+  slowTaskProvider: false,
+  didFocusProvider: false,
+  openFilesOnRenameProvider: false,
+};
+// Files written to the `.metals` directory of the workspace, to be opened in Zed
+const METALS_DIR = ".metals";
+const DOCTOR_FILE = "doctor";
+const STACKTRACE_FILE = "stacktrace.html";
+const LOG_FILE = "metals.log";
 
 const workdir = process.argv[1];
 const bin = process.argv[2];
@@ -49,21 +79,73 @@ const PROXY_HTTP_PORT_FILE = join(workdir, "proxy", PROXY_ID);
 const HELPER_DIR = join(homedir(), ".metals-zed");
 const HELPER_FILE = join(HELPER_DIR, "cmd.mjs");
 const HELPER_PORT_FILE = join(HELPER_DIR, `${PROXY_ID}.port`);
+// Secret required from HTTP clients, as any local process can connect to the port.
+// It's written next to the port files, readable only by the user.
+const SECRET = randomBytes(32).toString("hex");
+const PROXY_SECRET_FILE = `${PROXY_HTTP_PORT_FILE}.secret`;
+const HELPER_SECRET_FILE = join(HELPER_DIR, `${PROXY_ID}.secret`);
+const SECRET_FILE_MODE = 0o600;
 const command = process.platform === "win32" ? `"${bin}"` : bin;
 
 const lsp = spawn(command, args, { shell: process.platform === "win32" });
 const proxy = createLspProxy({ server: lsp, proxy: process });
 
+// Metals state reported by the health endpoint
+const state = {
+  initialized: false, // set once Zed confirms initialization
+  progress: new Map(), // running work done progress tasks: token -> title
+};
+
 proxy.on("client", (data, passthrough) => {
+  switch (data?.method) {
+    case "initialize":
+      data.params.initializationOptions = {
+        ...CLIENT_OPTIONS,
+        ...data.params.initializationOptions,
+      };
+      passthrough(data);
+      return;
+    case "initialized":
+      state.initialized = true;
+      break;
+  }
   passthrough();
 });
 proxy.on("server", (data, passthrough) => {
+  switch (data?.method) {
+    case "$/progress":
+      trackProgress(data.params);
+      break;
+    case "metals/executeClientCommand":
+      executeClientCommand(data.params);
+      return;
+    case "metals/quickPick":
+      quickPick(data.params, (result) => proxy.respond(data.id, result));
+      return;
+    case "metals/inputBox":
+      proxy.respond(data.id, inputBox(data.params));
+      return;
+  }
   passthrough();
 });
 
 const server = createServer(async (req, res) => {
+  if (!isAuthorized(req)) {
+    res.statusCode = 401;
+    res.end("Unauthorized");
+    return;
+  }
+
+  // Health check used by the extension before starting DAP
+  if (req.method === "GET" && req.url === HEALTH_PATH) {
+    res.statusCode = 200;
+    res.setHeader("Content-Type", "application/json");
+    res.end(JSON.stringify(health()));
+    return;
+  }
+
   if (req.method !== "POST") {
-    res.status = 405;
+    res.statusCode = 405;
     res.end("Method not allowed");
     return;
   }
@@ -73,7 +155,7 @@ const server = createServer(async (req, res) => {
     .catch(() => null);
 
   if (!data) {
-    res.status = 400;
+    res.statusCode = 400;
     res.end("Bad Request");
     return;
   }
@@ -89,7 +171,9 @@ const server = createServer(async (req, res) => {
     return;
   }
 
-  const result = await proxy.request(data.method, data.params);
+  const timeout =
+    Number.isInteger(data.timeout) && data.timeout > 0 ? data.timeout : TIMEOUT;
+  const result = await proxy.request(data.method, data.params, timeout);
   res.statusCode = 200;
   res.setHeader("Content-Type", "application/json");
   res.write(JSON.stringify(result));
@@ -97,12 +181,15 @@ const server = createServer(async (req, res) => {
 }).listen(HTTP_PORT, "127.0.0.1", () => {
   const portStr = server.address().port.toString();
   mkdirSync(dirname(PROXY_HTTP_PORT_FILE), { recursive: true });
+  // The secret goes first, so a client finding the port is able to authenticate
+  writeSecret(PROXY_SECRET_FILE);
   writeFileSync(PROXY_HTTP_PORT_FILE, portStr);
 
   // Mirror the port file to a workspace-independent location so tasks can find it,
   // and install the helper script there if Rust passed it in.
   try {
     mkdirSync(HELPER_DIR, { recursive: true });
+    writeSecret(HELPER_SECRET_FILE);
     writeFileSync(HELPER_PORT_FILE, portStr);
     const helperCode = process.env.METALS_ZED_HELPER_CODE;
     if (helperCode) {
@@ -113,28 +200,272 @@ const server = createServer(async (req, res) => {
   }
 });
 
-// Remove the helper port file on graceful shutdown so the next proxy startup
-// doesn't have to overwrite it - and so a helper invocation after shutdown
+// Remove the port and secret files on graceful shutdown so the next proxy startup
+// doesn't have to overwrite them - and so a helper invocation or DAP start after shutdown
 // fails fast with "no proxy" instead of "connection refused on a stale port".
+// A file is removed only if it's still this proxy's, as a proxy started meanwhile
+// for the same workspace (e.g. on restart) may have replaced it.
 process.on("exit", () => {
-  try {
-    unlinkSync(HELPER_PORT_FILE);
-  } catch {}
+  const port = server.address()?.port?.toString();
+  for (const [file, content] of [
+    [PROXY_HTTP_PORT_FILE, port],
+    [PROXY_SECRET_FILE, SECRET],
+    [HELPER_PORT_FILE, port],
+    [HELPER_SECRET_FILE, SECRET],
+  ]) {
+    try {
+      if (content && readFileSync(file, "utf8") === content) {
+        unlinkSync(file);
+      }
+    } catch {}
+  }
 });
+// Signals don't trigger "exit" on their own
+for (const signal of ["SIGINT", "SIGTERM"]) {
+  process.on(signal, () => process.exit(0));
+}
 
 // If Metals dies, drop with it so Zed respawns the whole pair cleanly.
 lsp.on("exit", () => process.exit(0));
 
+/**
+ * Write the secret to a file readable only by the user.
+ * The file is recreated, as `mode` doesn't apply to existing files.
+ *
+ * @param {string} file
+ */
+function writeSecret(file) {
+  try {
+    unlinkSync(file);
+  } catch {}
+  writeFileSync(file, SECRET, { mode: SECRET_FILE_MODE });
+}
+
+/**
+ * Check the `Authorization: Bearer <secret>` header of the request.
+ *
+ * @param {import("node:http").IncomingMessage} req
+ * @returns {boolean}
+ */
+function isAuthorized(req) {
+  const [scheme, token] = (req.headers.authorization ?? "").split(" ");
+  if (scheme !== "Bearer" || !token) {
+    return false;
+  }
+  const expected = Buffer.from(SECRET);
+  const actual = Buffer.from(token);
+  return (
+    actual.length === expected.length && timingSafeEqual(actual, expected)
+  );
+}
+
+/**
+ * Follow Metals' work done progress, to know when it's busy.
+ *
+ * @param {{ token: string | number, value: { kind: string, title?: string } }} params
+ */
+function trackProgress(params) {
+  switch (params?.value?.kind) {
+    case "begin":
+      state.progress.set(params.token, params.value.title ?? "");
+      break;
+    case "end":
+      state.progress.delete(params.token);
+      break;
+  }
+}
+
+/**
+ * State of Metals: "initializing", "importing" or "ready", with running tasks.
+ *
+ * @returns {{ status: string, tasks: string[] }}
+ */
+function health() {
+  const tasks = [...state.progress.values()];
+  let status = "ready";
+  if (!state.initialized) {
+    status = "initializing";
+  } else if (tasks.some((title) => IMPORT_TASKS.test(title))) {
+    status = "importing";
+  }
+  return { status, tasks };
+}
+
+/**
+ * Execute the client command sent by Metals.
+ * Zed ignores them, so the commands the proxy can't execute are reported to the user.
+ *
+ * @param {{ command: string, arguments?: any[] }} params
+ */
+function executeClientCommand(params) {
+  if (!runClientCommand(params)) {
+    showWarning(`Metals asked to run \`${params?.command}\`, which is not supported in Zed`);
+  }
+}
+
+/**
+ * @param {{ command: string, arguments?: any[] }} params
+ * @returns {boolean} whether the command was executed
+ */
+function runClientCommand(params) {
+  const args = params?.arguments ?? [];
+  switch (params?.command) {
+    case "metals-goto-location": {
+      const [location] = args;
+      if (!location?.uri) {
+        return false;
+      }
+      showDocument(location.uri, location.range);
+      return true;
+    }
+    case "metals-doctor-run":
+    case "metals-doctor-reload": {
+      const file = writeDoctor(args[0]);
+      // Zed reloads the file if it's already open
+      if (params.command === "metals-doctor-run") {
+        showDocument(pathToFileURL(file).href);
+      }
+      return true;
+    }
+    case "metals-show-stacktrace": {
+      const file = writeMetalsFile(STACKTRACE_FILE, args[0] ?? "");
+      showDocument(pathToFileURL(file).href);
+      return true;
+    }
+    case "metals-logs-toggle":
+      showDocument(pathToFileURL(join(process.cwd(), METALS_DIR, LOG_FILE)).href);
+      return true;
+    // Run the server command, e.g. from a link in a message
+    case "metals-echo-command":
+      proxy.send("workspace/executeCommand", { command: args[0] });
+      return true;
+    // Zed refreshes code lenses and diagnostics on its own
+    case "metals-model-refresh":
+    case "metals-diagnostics-focus":
+      return true;
+    default:
+      return false;
+  }
+}
+
+/**
+ * Let the user pick the item. Zed has no quick pick, so the items are buttons of a message.
+ *
+ * @typedef {{ id: string, label: string, description?: string }} QuickPickItem
+ * @param {{ items: QuickPickItem[], placeHolder?: string }} params
+ * @param {(result: { itemId: string } | { cancelled: true }) => void} respond
+ */
+function quickPick(params, respond) {
+  const items = (params?.items ?? []).map((item) => ({
+    id: item.id,
+    title: item.description ? `${item.label} (${item.description})` : item.label,
+  }));
+  if (items.length === 0) {
+    respond({ cancelled: true });
+    return;
+  }
+  proxy.clientRequest(
+    "window/showMessageRequest",
+    {
+      type: INFO_MESSAGE_TYPE,
+      message: params.placeHolder || "Metals: pick an option",
+      actions: items.map(({ title }) => ({ title })),
+    },
+    (response) => {
+      const item = items.find(({ title }) => title === response?.result?.title);
+      respond(item ? { itemId: item.id } : { cancelled: true });
+    },
+  );
+}
+
+/**
+ * Accept the default value, as Zed can't ask the user.
+ * Without one (e.g. the name of a new file), the action is cancelled and the user is told why.
+ *
+ * @param {{ prompt?: string, value?: string }} params
+ * @returns {{ value: string } | { cancelled: true }}
+ */
+function inputBox(params) {
+  if (params?.value) {
+    return { value: params.value };
+  }
+  const prompt = params?.prompt ? ` (${params.prompt})` : "";
+  showWarning(
+    `Metals asked for a value${prompt}, which Zed cannot prompt for, so the action was cancelled`,
+  );
+  return { cancelled: true };
+}
+
+/**
+ * @param {string} message
+ */
+function showWarning(message) {
+  proxy.notification("window/showMessage", { type: WARNING_MESSAGE_TYPE, message });
+}
+
+/**
+ * Ask Zed to open the document, selecting the range if given.
+ *
+ * @param {string} uri
+ * @param {any} [selection]
+ */
+function showDocument(uri, selection) {
+  proxy.clientRequest("window/showDocument", {
+    uri,
+    takeFocus: true,
+    ...(selection && { selection }),
+  });
+}
+
+/**
+ * Write the file to the `.metals` directory of the workspace.
+ *
+ * @param {string} name
+ * @param {string} content
+ * @returns {string} path of the file
+ */
+function writeMetalsFile(name, content) {
+  const file = join(process.cwd(), METALS_DIR, name);
+  mkdirSync(dirname(file), { recursive: true });
+  writeFileSync(file, content);
+  return file;
+}
+
+/**
+ * Write the doctor report, which is JSON or HTML depending on `doctorProvider`.
+ *
+ * @param {string} report
+ * @returns {string} path of the file
+ */
+function writeDoctor(report) {
+  const json = safeJsonParse(report);
+  return json === null
+    ? writeMetalsFile(`${DOCTOR_FILE}.html`, report ?? "")
+    : writeMetalsFile(`${DOCTOR_FILE}.json`, JSON.stringify(json, null, 2));
+}
+
 export function createLspProxy({
   server: { stdin: serverStdin, stdout: serverStdout, stderr: serverStderr },
   proxy: { stdin: proxyStdin, stdout: proxyStdout, stderr: proxyStderr },
 }) {
   const events = new EventEmitter();
   const queue = new Map();
+  // Requests sent by the proxy to Zed, whose responses mustn't reach Metals: id -> handler
+  const clientQueue = new Map();
   const nextid = iterid();
 
   proxyStdin.pipe(lspMessageSeparator()).on("data", (data) => {
-    events.emit("client", parse(data), () => serverStdin.write(data));
+    const message = parse(data);
+    const handler = message?.method === undefined && clientQueue.get(message?.id);
+    if (handler) {
+      clientQueue.delete(message.id);
+      handler(message);
+      return;
+    }
+    // A modified message may be passed through instead of the original one
+    events.emit("client", message, (modified) =>
+      serverStdin.write(modified ? stringify(modified) : data),
+    );
   });
 
   serverStdout.pipe(lspMessageSeparator()).on("data", (data) => {
@@ -164,29 +495,32 @@ export function createLspProxy({
     },
 
     /**
+     * Send a request and wait for the response. A request not answered in time is cancelled,
+     * and resolved with a JSON-RPC error, so the caller always gets a response.
      *
      * @param {string} method
      * @param {any} params
+     * @param {number} timeout in milliseconds
      * @returns Promise<any>
      */
-    request(method, params) {
-      return new Promise((resolve, reject) => {
+    request(method, params, timeout = TIMEOUT) {
+      return new Promise((resolve) => {
         const id = nextid();
         queue.set(id, resolve);
 
         setTimeout(() => {
           if (queue.has(id)) {
-            reject({
+            resolve({
               jsonrpc: "2.0",
               id,
               error: {
-                code: -32803,
-                message: "Request to language server timed out after 5000ms.",
+                code: TIMEOUT_ERROR_CODE,
+                message: `Request to language server timed out after ${timeout}ms.`,
               },
             });
             this.cancel(id);
           }
-        }, TIMEOUT);
+        }, timeout);
 
         serverStdin.write(stringify({ jsonrpc: "2.0", id, method, params }));
       });
@@ -206,6 +540,29 @@ export function createLspProxy({
       serverStdin.write(stringify({ jsonrpc: "2.0", id, method, params }));
     },
 
+    /**
+     * Respond to a request sent by the language server.
+     *
+     * @param {string | number} id
+     * @param {any} result
+     */
+    respond(id, result) {
+      serverStdin.write(stringify({ jsonrpc: "2.0", id, result }));
+    },
+
+    /**
+     * Send a request to the editor. Its response is passed to the handler, if any.
+     *
+     * @param {string} method
+     * @param {any} params
+     * @param {(response: any) => void} [handler]
+     */
+    clientRequest(method, params, handler = () => {}) {
+      const id = nextid();
+      clientQueue.set(id, handler);
+      proxyStdout.write(stringify({ jsonrpc: "2.0", id, method, params }));
+    },
+
     cancel(id) {
       queue.delete(id);
 
@@ -304,7 +661,7 @@ function stringify(content) {
   return (
     LENGTH_HEADER +
     NAME_VALUE_SEPARATOR +
-    json.length +
+    Buffer.byteLength(json) +
     CONTENT_SEPARATOR +
     json
   );
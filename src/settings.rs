@@ -0,0 +1,71 @@
+// Extension specific settings
+//
+// They are read from `lsp.metals.settings`, next to Metals user configuration,
+// so that all Metals related configuration is kept in one place.
+// Metals ignores the keys it doesn't know.
+
+use serde::Deserialize;
+use zed_extension_api::{self as zed, serde_json, settings::LspSettings};
+
+use crate::{LSP_DAP_NAME, error::ScalaError};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtensionSettings {
+    // Metals version to run: "latest", "snapshot" or an explicit version like "1.6.2"
+    pub server_version: Option<ServerVersion>,
+    // Extra server properties and JVM options for Metals, appended to the ones set by the extension.
+    // Unlike `lsp.metals.binary.arguments`, they don't disable the proxy (and thus DAP and tasks).
+    pub server_properties: Option<Vec<String>>,
+    // JDK used by Metals - this is Metals' own setting, but it's also used to start Metals
+    pub java_home: Option<String>,
+    // Time (in milliseconds) to wait for Metals to start the debugger, which may include compilation
+    pub debug_adapter_start_timeout: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(from = "String")]
+pub enum ServerVersion {
+    Latest,
+    Snapshot,
+    Exact(String),
+}
+
+impl From<String> for ServerVersion {
+    fn from(value: String) -> Self {
+        match value.trim() {
+            "latest" => ServerVersion::Latest,
+            "snapshot" => ServerVersion::Snapshot,
+            version => ServerVersion::Exact(version.to_string()),
+        }
+    }
+}
+
+impl ExtensionSettings {
+    // Server properties as arguments for the Metals launcher.
+    // JVM options have to be prefixed with `-J` for Coursier launchers, system properties are passed as they are.
+    pub fn server_arguments(&self) -> Vec<String> {
+        self.server_properties
+            .iter()
+            .flatten()
+            .map(|property| {
+                if property.starts_with("-X") {
+                    format!("-J{property}")
+                } else {
+                    property.clone()
+                }
+            })
+            .collect()
+    }
+
+    pub fn for_worktree(worktree: &zed::Worktree) -> Result<Self, ScalaError> {
+        LspSettings::for_worktree(LSP_DAP_NAME, worktree)
+            .ok()
+            .and_then(|lsp_settings| lsp_settings.settings)
+            .map_or(Ok(ExtensionSettings::default()), |settings| {
+                serde_json::from_value(settings).map_err(|e| {
+                    ScalaError::InvalidSettings(format!("`lsp.{LSP_DAP_NAME}.settings`: {e}"))
+                })
+            })
+    }
+}
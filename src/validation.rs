@@ -0,0 +1,348 @@
+// Validation of debug task definitions
+//
+// The definitions are checked before they're parsed, as errors of untagged enums in serde
+// don't tell which field is wrong. The rules follow `debug_adapter_schemas/Metals.json`.
+
+use zed_extension_api::serde_json::{Map, Value};
+
+// Expected type of a field
+enum FieldType {
+    String,
+    Boolean,
+    Port,
+    Milliseconds,
+    Strings,
+    StringMap,
+    OneOf(&'static [&'static str]),
+    // Strings in `NAME=value` form
+    Assignments,
+    BuildTargetIdentifier,
+    TestSuites,
+    // Non-empty array of test suites
+    Suites,
+}
+
+const REQUESTS: &[&str] = &["launch", "attach"];
+const RUN_TYPES: &[&str] = &["run", "runOrTestFile", "testFile", "testTarget"];
+
+const LAUNCH_FIELDS: &[(&str, FieldType)] = &[
+    ("request", FieldType::OneOf(REQUESTS)),
+    ("path", FieldType::String),
+    ("runType", FieldType::OneOf(RUN_TYPES)),
+    ("mainClass", FieldType::String),
+    ("testClass", FieldType::String),
+    ("target", FieldType::BuildTargetIdentifier),
+    ("requestData", FieldType::TestSuites),
+    ("buildTarget", FieldType::String),
+    ("sourceFile", FieldType::String),
+    ("args", FieldType::Strings),
+    ("jvmOptions", FieldType::Strings),
+    ("env", FieldType::StringMap),
+    ("envFile", FieldType::String),
+    ("noDebug", FieldType::Boolean),
+];
+
+const ATTACH_FIELDS: &[(&str, FieldType)] = &[
+    ("request", FieldType::OneOf(REQUESTS)),
+    ("buildTarget", FieldType::String),
+    ("hostName", FieldType::String),
+    ("port", FieldType::Port),
+    ("preAttachCommand", FieldType::String),
+    ("connectTimeout", FieldType::Milliseconds),
+    ("retryInterval", FieldType::Milliseconds),
+];
+
+// Fields defining what to launch - exactly one of them is required
+const ENTRY_FIELDS: &[&str] = &["path", "mainClass", "testClass", "requestData"];
+// Fields valid only together with an entry field
+const ENTRY_OPTIONS: &[(&str, &str)] = &[("runType", "path"), ("target", "requestData")];
+
+// Check the debug task definition, reporting the first invalid field
+pub fn validate(config: &Value) -> Result<(), String> {
+    let config = config
+        .as_object()
+        .ok_or("The configuration must be a JSON object")?;
+    let request = config
+        .get("request")
+        .ok_or("Missing required field `request`, which must be one of launch, attach")?;
+    check_type("request", request, &FieldType::OneOf(REQUESTS))?;
+
+    match request.as_str() {
+        Some("launch") => {
+            check_fields(config, "launch", LAUNCH_FIELDS)?;
+            check_entry(config)
+        }
+        _ => check_fields(config, "attach", ATTACH_FIELDS),
+    }
+}
+
+fn check_fields(
+    config: &Map<String, Value>,
+    request: &str,
+    fields: &[(&str, FieldType)],
+) -> Result<(), String> {
+    // Optional fields may be given as null
+    for (name, value) in config.iter().filter(|(_, value)| !value.is_null()) {
+        let field_type = field_type(fields, name).ok_or_else(|| {
+            format!(
+                "Unknown field `{name}` for {request} request. Valid fields: {}",
+                field_names(fields)
+            )
+        })?;
+        check_type(name, value, field_type)?;
+    }
+    Ok(())
+}
+
+fn check_entry(config: &Map<String, Value>) -> Result<(), String> {
+    let entries = ENTRY_FIELDS
+        .iter()
+        .filter(|field| is_set(config, field))
+        .collect::<Vec<_>>();
+    match entries.as_slice() {
+        [] => {
+            return Err(format!(
+                "Launch request requires one of {}",
+                quoted(ENTRY_FIELDS).join(", ")
+            ));
+        }
+        [_] => {}
+        [first, second, ..] => {
+            return Err(format!("`{first}` and `{second}` are mutually exclusive"));
+        }
+    }
+
+    for (option, entry) in ENTRY_OPTIONS {
+        if is_set(config, option) && !is_set(config, entry) {
+            return Err(format!("`{option}` is valid only together with `{entry}`"));
+        }
+    }
+    Ok(())
+}
+
+fn is_set(config: &Map<String, Value>, field: &str) -> bool {
+    config.get(field).is_some_and(|value| !value.is_null())
+}
+
+fn check_type(name: &str, value: &Value, field_type: &FieldType) -> Result<(), String> {
+    let valid = match field_type {
+        FieldType::String => value.is_string(),
+        FieldType::Boolean => value.is_boolean(),
+        FieldType::Port => value
+            .as_u64()
+            .is_some_and(|port| (1..=u64::from(u16::MAX)).contains(&port)),
+        FieldType::Milliseconds => value.as_u64().is_some_and(|ms| ms > 0),
+        FieldType::Strings => is_strings(value),
+        FieldType::StringMap => value
+            .as_object()
+            .is_some_and(|map| map.values().all(Value::is_string)),
+        FieldType::OneOf(values) => value.as_str().is_some_and(|value| values.contains(&value)),
+        FieldType::Assignments => value.as_array().is_some_and(|values| {
+            values
+                .iter()
+                .all(|value| value.as_str().is_some_and(|value| value.contains('=')))
+        }),
+        FieldType::BuildTargetIdentifier => {
+            return check_object(name, value, &[("uri", FieldType::String)], &["uri"]);
+        }
+        FieldType::TestSuites => {
+            return check_object(
+                name,
+                value,
+                &[
+                    ("suites", FieldType::Suites),
+                    ("jvmOptions", FieldType::Strings),
+                    ("environmentVariables", FieldType::Assignments),
+                ],
+                &["suites"],
+            );
+        }
+        FieldType::Suites => return check_suites(name, value),
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("`{name}` must be {}", describe(field_type)))
+    }
+}
+
+fn check_suites(name: &str, value: &Value) -> Result<(), String> {
+    let suites = value
+        .as_array()
+        .filter(|suites| !suites.is_empty())
+        .ok_or(format!("`{name}` must be {}", describe(&FieldType::Suites)))?;
+    for (index, suite) in suites.iter().enumerate() {
+        check_object(
+            &format!("{name}[{index}]"),
+            suite,
+            &[
+                ("className", FieldType::String),
+                ("tests", FieldType::Strings),
+            ],
+            &["className"],
+        )?;
+    }
+    Ok(())
+}
+
+// Check fields of a nested object
+fn check_object(
+    name: &str,
+    value: &Value,
+    fields: &[(&str, FieldType)],
+    required: &[&str],
+) -> Result<(), String> {
+    let object = value
+        .as_object()
+        .ok_or(format!("`{name}` must be an object"))?;
+    for field in required {
+        if !object.contains_key(*field) {
+            return Err(format!("`{name}` requires field `{field}`"));
+        }
+    }
+    for (field, value) in object {
+        let field_type = field_type(fields, field).ok_or_else(|| {
+            format!(
+                "Unknown field `{name}.{field}`. Valid fields: {}",
+                field_names(fields)
+            )
+        })?;
+        check_type(&format!("{name}.{field}"), value, field_type)?;
+    }
+    Ok(())
+}
+
+fn field_type<'a>(fields: &'a [(&str, FieldType)], name: &str) -> Option<&'a FieldType> {
+    fields
+        .iter()
+        .find(|(field, _)| *field == name)
+        .map(|(_, field_type)| field_type)
+}
+
+fn field_names(fields: &[(&str, FieldType)]) -> String {
+    fields
+        .iter()
+        .map(|(field, _)| *field)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn is_strings(value: &Value) -> bool {
+    value
+        .as_array()
+        .is_some_and(|values| values.iter().all(Value::is_string))
+}
+
+fn describe(field_type: &FieldType) -> String {
+    match field_type {
+        FieldType::String => "a string".to_string(),
+        FieldType::Boolean => "true or false".to_string(),
+        FieldType::Port => "an integer between 1 and 65535".to_string(),
+        FieldType::Milliseconds => "a positive integer (milliseconds)".to_string(),
+        FieldType::Strings => "an array of strings".to_string(),
+        FieldType::StringMap => "an object with string values".to_string(),
+        FieldType::Assignments => "an array of strings in NAME=value form".to_string(),
+        FieldType::OneOf(values) => format!("one of {}", values.join(", ")),
+        FieldType::BuildTargetIdentifier => "an object with `uri`".to_string(),
+        FieldType::TestSuites => "an object with `suites`".to_string(),
+        FieldType::Suites => "a non-empty array of test suites".to_string(),
+    }
+}
+
+fn quoted(fields: &[&str]) -> Vec<String> {
+    fields.iter().map(|field| format!("`{field}`")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use zed_extension_api::{self as zed, serde_json};
+
+    use super::*;
+    use crate::dap::Debugger;
+
+    fn validate_json(config: &str) -> Result<(), String> {
+        validate(&serde_json::from_str(config).unwrap())
+    }
+
+    #[test]
+    fn generic_launch_is_valid() {
+        let config = Debugger::convert_generic_config(zed::DebugConfig {
+            label: "Run".to_string(),
+            adapter: "Metals".to_string(),
+            request: zed::DebugRequest::Launch(zed::LaunchRequest {
+                program: "src/main/scala/Main.scala".to_string(),
+                cwd: Some("/project".to_string()),
+                args: vec![],
+                envs: vec![],
+            }),
+            stop_on_entry: None,
+        })
+        .unwrap();
+        assert_eq!(validate(&serde_json::to_value(config).unwrap()), Ok(()));
+    }
+
+    #[test]
+    fn null_fields_are_skipped() {
+        assert_eq!(
+            validate_json(r#"{"request": "launch", "path": "Main.scala", "runType": null}"#),
+            Ok(())
+        );
+        assert!(
+            validate_json(r#"{"request": "launch", "path": null}"#)
+                .unwrap_err()
+                .contains("requires one of")
+        );
+    }
+
+    #[test]
+    fn unknown_field_is_reported() {
+        let error = validate_json(r#"{"request": "attach", "mainClass": "Main"}"#).unwrap_err();
+        assert!(error.starts_with("Unknown field `mainClass` for attach request"));
+    }
+
+    #[test]
+    fn invalid_types_are_reported() {
+        assert_eq!(
+            validate_json(r#"{"request": "attach", "port": 70000}"#),
+            Err("`port` must be an integer between 1 and 65535".to_string())
+        );
+        assert_eq!(
+            validate_json(r#"{"request": "launch", "mainClass": "Main", "args": "a b"}"#),
+            Err("`args` must be an array of strings".to_string())
+        );
+        assert_eq!(
+            validate_json(r#"{"request": "debug"}"#),
+            Err("`request` must be one of launch, attach".to_string())
+        );
+    }
+
+    #[test]
+    fn entry_fields_are_exclusive() {
+        assert_eq!(
+            validate_json(r#"{"request": "launch", "mainClass": "Main", "testClass": "Test"}"#),
+            Err("`mainClass` and `testClass` are mutually exclusive".to_string())
+        );
+        assert_eq!(
+            validate_json(r#"{"request": "launch", "mainClass": "Main", "runType": "run"}"#),
+            Err("`runType` is valid only together with `path`".to_string())
+        );
+    }
+
+    #[test]
+    fn nested_test_suites_are_checked() {
+        assert_eq!(
+            validate_json(
+                r#"{"request": "launch", "requestData": {"suites": [{"className": "A", "tests": ["t"]}]}}"#
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            validate_json(r#"{"request": "launch", "requestData": {"suites": [{"tests": []}]}}"#),
+            Err("`requestData.suites[0]` requires field `className`".to_string())
+        );
+        assert_eq!(
+            validate_json(r#"{"request": "launch", "requestData": {"suites": []}}"#),
+            Err("`requestData.suites` must be a non-empty array of test suites".to_string())
+        );
+    }
+}
@@ -0,0 +1,250 @@
+// Placeholders in debug configurations
+//
+// Zed substitutes its task variables (like `$ZED_FILE`) before the configuration reaches the extension.
+// On top of them, the extension expands the following placeholders in all string values,
+// so that debug configurations don't depend on the machine:
+// - `${worktreeRoot}` - root directory of the worktree,
+// - `${userHome}` - home directory of the user,
+// - `${env:NAME}` - environment variable (empty if not set),
+// - `${buildTargetDir:NAME}` - base directory of the build target (as exported to Bloop by the build tool).
+// `$${` stands for a literal `${`, e.g. in logging patterns passed in `jvmOptions`.
+// Shell commands, like `preAttachCommand`, are left as they are, so that the shell expands their `${VAR}`s,
+// and so are the names of tests, which are code rather than configuration.
+
+use zed_extension_api::{self as zed, serde_json::Value};
+
+use crate::bloop;
+
+const ESCAPED_PLACEHOLDER: &str = "$${";
+// Fields left as they are, by their paths (array elements share the path of the array)
+const LITERAL_FIELDS: &[&str] = &["preAttachCommand", "requestData.suites.tests"];
+
+// Expand placeholders in all strings of the configuration
+pub fn substitute(config: &mut Value, worktree: &zed::Worktree) -> Result<(), String> {
+    let variables = Variables {
+        root: worktree.root_path(),
+        env: worktree.shell_env(),
+        build_target_dir: &|name| bloop::project(worktree, name).map(|project| project.directory),
+    };
+    substitute_with(config, "", &|placeholder| variables.resolve(placeholder))
+}
+
+// Expand placeholders in all strings of the value at the path, except for the literal fields
+fn substitute_with(
+    value: &mut Value,
+    path: &str,
+    resolve: &impl Fn(&str) -> Result<String, String>,
+) -> Result<(), String> {
+    match value {
+        Value::String(string) => *string = expand(string, resolve)?,
+        Value::Array(values) => {
+            for value in values {
+                substitute_with(value, path, resolve)?;
+            }
+        }
+        Value::Object(fields) => {
+            for (name, value) in fields {
+                let path = if path.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{path}.{name}")
+                };
+                if !LITERAL_FIELDS.contains(&path.as_str()) {
+                    substitute_with(value, &path, resolve)?;
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+// Replace `${...}` placeholders with their values, and `$${` with `${`
+fn expand(
+    string: &str,
+    resolve: &impl Fn(&str) -> Result<String, String>,
+) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = string;
+    while let Some(start) = rest.find("${") {
+        if rest[..start + 2].ends_with(ESCAPED_PLACEHOLDER) {
+            expanded.push_str(&rest[..start - 1]);
+            expanded.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        expanded.push_str(&rest[..start]);
+        let placeholder = &rest[start + 2..];
+        let end = placeholder
+            .find('}')
+            .ok_or(format!("Missing closing `}}` in `{string}`"))?;
+        expanded.push_str(&resolve(&placeholder[..end])?);
+        rest = &placeholder[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+struct Variables<'a> {
+    root: String,
+    env: zed::EnvVars,
+    build_target_dir: &'a dyn Fn(&str) -> Result<String, String>,
+}
+
+impl Variables<'_> {
+    fn resolve(&self, placeholder: &str) -> Result<String, String> {
+        match placeholder.split_once(':') {
+            None if placeholder == "worktreeRoot" => Ok(self.root.clone()),
+            None if placeholder == "userHome" => self
+                .env_var("HOME")
+                .or_else(|| self.env_var("USERPROFILE"))
+                .ok_or("Cannot find the home directory for `${userHome}`".to_string()),
+            Some(("env", name)) => Ok(self.env_var(name).unwrap_or_default()),
+            Some(("buildTargetDir", name)) => (self.build_target_dir)(name)
+                .map_err(|e| format!("Cannot resolve `${{buildTargetDir:{name}}}`: {e}")),
+            _ => Err(format!(
+                "Unknown placeholder `${{{placeholder}}}`. Supported placeholders: `${{worktreeRoot}}`, `${{userHome}}`, `${{env:NAME}}` and `${{buildTargetDir:NAME}}` (write `$${{` for a literal `${{`)"
+            )),
+        }
+    }
+
+    fn env_var(&self, name: &str) -> Option<String> {
+        self.env
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zed_extension_api::serde_json::json;
+
+    use super::*;
+
+    fn build_target_dir(name: &str) -> Result<String, String> {
+        match name {
+            "core" => Ok("/project/modules/core".to_string()),
+            _ => Err(format!("No Bloop project `{name}`")),
+        }
+    }
+
+    fn variables(env: &[(&str, &str)]) -> Variables<'static> {
+        Variables {
+            root: "/project".to_string(),
+            env: env
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            build_target_dir: &build_target_dir,
+        }
+    }
+
+    fn substitute_json(mut config: Value) -> Result<Value, String> {
+        let variables = variables(&[("HOME", "/home/user"), ("PROFILE", "dev")]);
+        substitute_with(&mut config, "", &|placeholder| {
+            variables.resolve(placeholder)
+        })?;
+        Ok(config)
+    }
+
+    #[test]
+    fn placeholders_are_resolved() {
+        let variables = variables(&[("HOME", "/home/user"), ("PROFILE", "dev")]);
+        assert_eq!(
+            variables.resolve("worktreeRoot"),
+            Ok("/project".to_string())
+        );
+        assert_eq!(variables.resolve("userHome"), Ok("/home/user".to_string()));
+        assert_eq!(variables.resolve("env:PROFILE"), Ok("dev".to_string()));
+        assert_eq!(variables.resolve("env:UNDEFINED"), Ok(String::new()));
+        assert_eq!(
+            variables.resolve("buildTargetDir:core"),
+            Ok("/project/modules/core".to_string())
+        );
+        assert_eq!(
+            variables.resolve("buildTargetDir:web"),
+            Err("Cannot resolve `${buildTargetDir:web}`: No Bloop project `web`".to_string())
+        );
+        assert!(
+            variables
+                .resolve("workspaceFolder")
+                .unwrap_err()
+                .starts_with("Unknown placeholder `${workspaceFolder}`")
+        );
+    }
+
+    #[test]
+    fn user_home_falls_back_to_user_profile() {
+        assert_eq!(
+            variables(&[("USERPROFILE", "C:\\Users\\user")]).resolve("userHome"),
+            Ok("C:\\Users\\user".to_string())
+        );
+        assert!(variables(&[]).resolve("userHome").is_err());
+    }
+
+    #[test]
+    fn placeholders_are_expanded() {
+        assert_eq!(
+            substitute_json(json!("${worktreeRoot}/.env")),
+            Ok(json!("/project/.env"))
+        );
+        assert_eq!(
+            substitute_json(json!("a${env:UNDEFINED}b${env:PROFILE}")),
+            Ok(json!("abdev"))
+        );
+        assert_eq!(
+            substitute_json(json!("$HOME and $")),
+            Ok(json!("$HOME and $"))
+        );
+    }
+
+    #[test]
+    fn escaped_placeholders_are_literal() {
+        assert_eq!(
+            substitute_json(json!("-Dlog.pattern=$${LOG_LEVEL} in ${env:PROFILE}")),
+            Ok(json!("-Dlog.pattern=${LOG_LEVEL} in dev"))
+        );
+        assert_eq!(substitute_json(json!("$${")), Ok(json!("${")));
+    }
+
+    #[test]
+    fn invalid_placeholders_are_reported() {
+        assert_eq!(
+            substitute_json(json!("${worktreeRoot")),
+            Err("Missing closing `}` in `${worktreeRoot`".to_string())
+        );
+        assert!(
+            substitute_json(json!("%d{HH:mm} ${x}"))
+                .unwrap_err()
+                .starts_with("Unknown placeholder `${x}`")
+        );
+    }
+
+    #[test]
+    fn nested_strings_are_expanded_except_literal_fields() {
+        let config = json!({
+            "envFile": "${worktreeRoot}/.env",
+            "args": ["--dir", "${worktreeRoot}"],
+            "env": { "ROOT": "${worktreeRoot}" },
+            "noDebug": true,
+            "preAttachCommand": "ssh -N -L 5005:localhost:5005 ${HOST}",
+            "requestData": {
+                "suites": [{ "className": "${env:PROFILE}", "tests": ["renders ${name}"] }],
+            },
+        });
+        assert_eq!(
+            substitute_json(config),
+            Ok(json!({
+                "envFile": "/project/.env",
+                "args": ["--dir", "/project"],
+                "env": { "ROOT": "/project" },
+                "noDebug": true,
+                "preAttachCommand": "ssh -N -L 5005:localhost:5005 ${HOST}",
+                "requestData": {
+                    "suites": [{ "className": "dev", "tests": ["renders ${name}"] }],
+                },
+            }))
+        );
+    }
+}